@@ -0,0 +1,32 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use tracing::info_span;
+use tracing_span_dump::SpanDumpLayer;
+use tracing_subscriber::prelude::*;
+
+fn span_creation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("span_creation");
+    for capture_fields in [false, true] {
+        let layer = SpanDumpLayer::builder()
+            .capture_fields(capture_fields)
+            .build();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        let suffix = if capture_fields {
+            "capture"
+        } else {
+            "no_capture"
+        };
+
+        tracing::subscriber::with_default(subscriber, || {
+            group.bench_function(format!("no_fields/{suffix}"), |b| {
+                b.iter(|| info_span!("request"))
+            });
+            group.bench_function(format!("fields/{suffix}"), |b| {
+                b.iter(|| info_span!("request", method = "GET", status = 200))
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, span_creation);
+criterion_main!(benches);
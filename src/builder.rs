@@ -0,0 +1,409 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::Instant;
+
+use crate::limits::NameLimits;
+use crate::record::FieldRules;
+use crate::ParentStrategy;
+use crate::{Shared, SpanDumpLayer, SpanRecord, SpanSnapshot};
+
+#[derive(Clone, Default)]
+pub(crate) struct Config {
+    pub(crate) capture_fields: bool,
+    pub(crate) field_rules: FieldRules,
+    pub(crate) postmortem_capacity: usize,
+    pub(crate) recent_per_name: usize,
+    pub(crate) retain_closed_children: usize,
+    pub(crate) threshold: Option<Threshold>,
+    pub(crate) capture_ancestry: bool,
+    pub(crate) parent_strategy: ParentStrategy,
+    pub(crate) sibling_index: bool,
+    pub(crate) capture_if: Option<Arc<CaptureIf>>,
+    pub(crate) required_fields: Vec<String>,
+    pub(crate) keep_rejected: bool,
+    pub(crate) sample_rate: Option<f64>,
+    pub(crate) name_classifier: Option<Arc<NameClassifier>>,
+    pub(crate) record_transform: Option<Arc<RecordTransform>>,
+    pub(crate) clock: Option<Arc<Clock>>,
+    pub(crate) capture_on_event: Option<CaptureOnEvent>,
+    pub(crate) log_lifecycle: Option<tracing::Level>,
+    pub(crate) track_enters: bool,
+    pub(crate) count_events: bool,
+    pub(crate) capture_on_enter: bool,
+    pub(crate) name_limits: HashMap<String, usize>,
+    pub(crate) on_name_limit_exceeded: Option<Arc<NameLimitCallback>>,
+    #[cfg(feature = "hdrhistogram")]
+    pub(crate) latency_histograms: bool,
+    #[cfg(feature = "backtrace")]
+    pub(crate) backtrace_rate: Option<f64>,
+}
+
+pub(crate) type CaptureIf = dyn Fn(&tracing::Metadata<'_>) -> bool + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct CaptureOnEvent {
+    pub(crate) filter: Arc<CaptureIf>,
+    pub(crate) callback: Arc<dyn Fn(SpanSnapshot) + Send + Sync>,
+}
+
+pub(crate) type NameClassifier = dyn Fn(&str) -> Option<u32> + Send + Sync;
+
+pub(crate) type RecordTransform = dyn Fn(&mut SpanRecord) + Send + Sync;
+
+pub(crate) type NameLimitCallback = dyn Fn(&'static str, usize) + Send + Sync;
+
+pub(crate) type Clock = dyn Fn() -> Instant + Send + Sync;
+
+impl Config {
+    pub(crate) fn now(&self) -> Instant {
+        self.clock
+            .as_ref()
+            .map_or_else(Instant::now, |clock| clock())
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Threshold {
+    pub(crate) limit: usize,
+    pub(crate) callback: Arc<dyn Fn(usize) + Send + Sync>,
+}
+
+#[derive(Default)]
+pub struct Builder {
+    config: Config,
+}
+
+impl Builder {
+    /// Record span fields onto each record, including values recorded after
+    /// creation. Off by default.
+    pub fn capture_fields(mut self, enabled: bool) -> Self {
+        self.config.capture_fields = enabled;
+        self
+    }
+
+    /// Store [`REDACTED`](crate::REDACTED) instead of the value of captured
+    /// fields with exactly these names, e.g. `&["password", "token"]`. The
+    /// values are never formatted, so they do not end up in snapshots,
+    /// dumps or events. Adds to the names from earlier calls.
+    pub fn redact_fields(mut self, names: &[&str]) -> Self {
+        self.config
+            .field_rules
+            .redact
+            .extend(names.iter().map(|name| name.to_string()));
+        self
+    }
+
+    /// Cut captured field values longer than `len` bytes down to at most
+    /// `len`, on a character boundary, followed by
+    /// [`TRUNCATED`](crate::TRUNCATED). Formatting stops once the limit is
+    /// reached, so the full value is never held.
+    pub fn max_field_len(mut self, len: usize) -> Self {
+        self.config.field_rules.max_len = Some(len);
+        self
+    }
+
+    /// Capture at most `count` fields per span. Fields beyond the first
+    /// `count` are dropped, while later values for fields already captured
+    /// still replace them.
+    pub fn max_fields_per_span(mut self, count: usize) -> Self {
+        self.config.field_rules.max_fields = Some(count);
+        self
+    }
+
+    /// Retain the last `capacity` closed spans for [`SpanDumpLayer::postmortem`].
+    /// Combine with [`Builder::capture_fields`] to keep their field values.
+    pub fn postmortem(mut self, capacity: usize) -> Self {
+        self.config.postmortem_capacity = capacity;
+        self
+    }
+
+    /// Retain the last `capacity` closed spans of every name for
+    /// [`SpanDumpLayer::recent_for_name`], as samples that are kept
+    /// regardless of how busy other names are. Memory grows with the number
+    /// of distinct span names.
+    pub fn retain_recent_per_name(mut self, capacity: usize) -> Self {
+        self.config.recent_per_name = capacity;
+        self
+    }
+
+    /// Keep closed spans while their parent is open, up to `max` in total,
+    /// for [`SpanDumpLayer::snapshot_with_closed`]. A span's kept children
+    /// stay with it if it is kept in turn, and are dropped once it is not,
+    /// so a request's whole tree is available until its root closes.
+    ///
+    /// Once `max` spans are kept further closing spans are dropped as usual,
+    /// bounding the memory a long-lived root can hold on to.
+    pub fn retain_closed_children(mut self, max: usize) -> Self {
+        self.config.retain_closed_children = max;
+        self
+    }
+
+    /// Calls `callback` with the open span count the first time it rises
+    /// above `limit`. The callback is re-armed once the count drops back
+    /// below `limit`, and is invoked without holding the layer's lock.
+    pub fn on_threshold_exceeded(
+        mut self,
+        limit: usize,
+        callback: impl Fn(usize) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.threshold = Some(Threshold {
+            limit,
+            callback: Arc::new(callback),
+        });
+        self
+    }
+
+    /// Alarm when more than `limit` spans named `name` are open at once, for
+    /// spans that stand for a bounded resource such as a connection pool.
+    /// May be called for several names.
+    ///
+    /// The first time the count rises above `limit` a `WARN` event is sent
+    /// on the [`LIFECYCLE_TARGET`](crate::LIFECYCLE_TARGET) target, or the
+    /// [`Builder::on_name_limit_exceeded`] callback is called instead. As
+    /// with [`Builder::on_threshold_exceeded`], the alarm is re-armed once
+    /// the count drops back below `limit`.
+    pub fn max_spans_for_name(mut self, name: impl Into<String>, limit: usize) -> Self {
+        self.config.name_limits.insert(name.into(), limit);
+        self
+    }
+
+    /// Calls `callback` with the span name and its open count instead of
+    /// sending a warning when a [`Builder::max_spans_for_name`] limit is
+    /// exceeded. The callback is invoked without holding the layer's lock.
+    pub fn on_name_limit_exceeded(
+        mut self,
+        callback: impl Fn(&'static str, usize) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.on_name_limit_exceeded = Some(Arc::new(callback));
+        self
+    }
+
+    /// Only track spans whose metadata matches `predicate`.
+    ///
+    /// Rejected spans are still created and seen by the other layers of the
+    /// subscriber, they are just not recorded by this one unless
+    /// [`Builder::keep_rejected`] is enabled. The number of rejected spans is
+    /// reported in [`LayerStats`](crate::LayerStats).
+    pub fn capture_if(
+        mut self,
+        predicate: impl Fn(&tracing::Metadata<'_>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.config.capture_if = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Only track spans created with a value for the field `name`, e.g.
+    /// `"request_id"` to keep only request-scoped spans. Calling this again
+    /// requires every named field.
+    ///
+    /// Fields declared as [`field::Empty`](tracing::field::Empty) and
+    /// recorded later do not count, as presence is checked once when the
+    /// span is created. Rejected spans are counted like those rejected by
+    /// [`Builder::capture_if`].
+    pub fn require_field(mut self, name: impl Into<String>) -> Self {
+        self.config.required_fields.push(name.into());
+        self
+    }
+
+    /// Track the spans rejected by [`Builder::capture_if`] and
+    /// [`Builder::require_field`] anyway, marked
+    /// [`FilterDecision::Rejected`](crate::FilterDecision::Rejected) in
+    /// [`SpanRecord::filter`](crate::SpanRecord::filter) and in verbose
+    /// dumps, to see what a filter would leave out while tuning it. They
+    /// are still counted as filtered in [`LayerStats`](crate::LayerStats).
+    pub fn keep_rejected(mut self, enabled: bool) -> Self {
+        self.config.keep_rejected = enabled;
+        self
+    }
+
+    /// Only track about `rate` (between 0 and 1) of the spans that pass
+    /// [`Builder::capture_if`], to bound the layer's overhead and memory on
+    /// very busy services. Every span is tracked by default.
+    ///
+    /// Spans are sampled without regard to their parents, so by design
+    /// snapshots are incomplete: trees have gaps, and children of
+    /// sampled-out spans show up as roots. Spans that are not sampled in are
+    /// never inserted, so their later records, enters and close are ignored.
+    /// The counts are reported in [`LayerStats`](crate::LayerStats).
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.config.sample_rate = (rate < 1.0).then_some(rate.max(0.0));
+        self
+    }
+
+    /// Tag each record with the class `classifier` assigns to its span name,
+    /// e.g. an application's own "io" or "compute" enum cast to `u32`, for
+    /// [`SpanSnapshot::group_by_class`] and
+    /// [`SnapshotFilter::class`](crate::SnapshotFilter::class). The
+    /// classifier runs for every new span, so it should be cheap.
+    pub fn with_name_classifier(
+        mut self,
+        classifier: impl Fn(&str) -> Option<u32> + Send + Sync + 'static,
+    ) -> Self {
+        self.config.name_classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// Calls `transform` on every record just before it is stored, e.g. to
+    /// strip a common target prefix or add a derived field. Whatever it
+    /// leaves in the record is what snapshots and events see.
+    ///
+    /// It runs on the thread creating the span (or, with
+    /// [`Builder::capture_on_enter`], first entering it) while that thread
+    /// is inside the subscriber. Keep it fast, and do not create spans or
+    /// call back into the layer from it.
+    pub fn with_record_transform(
+        mut self,
+        transform: impl Fn(&mut SpanRecord) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.record_transform = Some(Arc::new(transform));
+        self
+    }
+
+    /// Choose which span becomes each record's
+    /// [`SpanRecord::parent`](crate::SpanRecord::parent), and so how spans
+    /// nest in trees. Defaults to [`ParentStrategy::ContextualPreferred`].
+    pub fn parent_strategy(mut self, strategy: ParentStrategy) -> Self {
+        self.config.parent_strategy = strategy;
+        self
+    }
+
+    /// Record each span's position among all the children its parent has
+    /// opened in [`SpanRecord::sibling_index`], to reconstruct the order work
+    /// was started in. Off by default. The index is assigned as the span is
+    /// stored, after [`Builder::with_record_transform`] has run.
+    pub fn sibling_index(mut self, enabled: bool) -> Self {
+        self.config.sibling_index = enabled;
+        self
+    }
+
+    /// Store each span's full chain of ancestor ids on its record when it is
+    /// created, so the path survives ancestors closing first. Off by default.
+    pub fn capture_ancestry(mut self, enabled: bool) -> Self {
+        self.config.capture_ancestry = enabled;
+        self
+    }
+
+    /// Calls `callback` with a snapshot of the open spans whenever an event
+    /// whose metadata matches `filter` is recorded, e.g. every `ERROR`.
+    ///
+    /// The callback runs on the thread that emitted the event, without
+    /// holding the layer's lock. Events emitted from inside the callback do
+    /// not trigger it again.
+    ///
+    /// With the `tracing-log` feature, records bridged from the `log` crate
+    /// are matched on their own target, level and location rather than the
+    /// bridge's generic `log` callsite.
+    pub fn capture_on_event(
+        mut self,
+        filter: impl Fn(&tracing::Metadata<'_>) -> bool + Send + Sync + 'static,
+        callback: impl Fn(SpanSnapshot) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.capture_on_event = Some(CaptureOnEvent {
+            filter: Arc::new(filter),
+            callback: Arc::new(callback),
+        });
+        self
+    }
+
+    /// Emit an event at `level` on the [`LIFECYCLE_TARGET`](crate::LIFECYCLE_TARGET)
+    /// target whenever a tracked span opens or closes, so the span
+    /// lifecycle shows up in the normal log output. Off by default.
+    ///
+    /// The events are sent to the subscriber the layer is installed in and
+    /// never trigger [`Builder::capture_on_event`].
+    pub fn log_lifecycle(mut self, level: tracing::Level) -> Self {
+        self.config.log_lifecycle = Some(level);
+        self
+    }
+
+    /// Track how long each span spends entered, see
+    /// [`SpanRecord::busy_duration`](crate::SpanRecord::busy_duration) and
+    /// [`SpanRecord::idle_duration`](crate::SpanRecord::idle_duration), and
+    /// how deep their scope gets, see
+    /// [`SpanTiming::max_enter_depth`](crate::SpanTiming::max_enter_depth).
+    /// Off by default, as it takes the layer's write lock on every enter and
+    /// exit.
+    pub fn track_enters(mut self, enabled: bool) -> Self {
+        self.config.track_enters = enabled;
+        self
+    }
+
+    /// Count the events emitted in each span, see
+    /// [`SpanRecord::event_count`](crate::SpanRecord::event_count). An event
+    /// counts towards its explicit parent or else the current span. Off by
+    /// default, as it takes the layer's write lock on every event.
+    pub fn count_events(mut self, enabled: bool) -> Self {
+        self.config.count_events = enabled;
+        self
+    }
+
+    /// Only track spans once they are first entered, so spans that are
+    /// created but never entered, such as instrumented futures that are
+    /// never polled, are left out. Off by default.
+    ///
+    /// Until then the record is kept in the registry's span extensions. Its
+    /// age still counts from when the span was created.
+    pub fn capture_on_enter(mut self, enabled: bool) -> Self {
+        self.config.capture_on_enter = enabled;
+        self
+    }
+
+    /// Use `clock` instead of [`Instant::now`] for span open and close times
+    /// and for the ages and durations measured from records and snapshots,
+    /// so tests can control time.
+    pub fn with_clock(mut self, clock: impl Fn() -> Instant + Send + Sync + 'static) -> Self {
+        self.config.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Record the duration of every closed span into a histogram per span
+    /// name, see [`SpanDumpLayer::latency_histogram`]. Off by default.
+    #[cfg(feature = "hdrhistogram")]
+    pub fn latency_histograms(mut self, enabled: bool) -> Self {
+        self.config.latency_histograms = enabled;
+        self
+    }
+
+    /// Capture a backtrace of every tracked span's creation into
+    /// [`SpanRecord::backtrace`], shown by the verbose dump. Off by default:
+    /// capturing is expensive, so this is meant for tracking down where
+    /// unexpected spans come from. See also
+    /// [`Builder::sample_backtraces`].
+    #[cfg(feature = "backtrace")]
+    pub fn capture_backtrace(mut self, enabled: bool) -> Self {
+        self.config.backtrace_rate = enabled.then_some(1.0);
+        self
+    }
+
+    /// Like [`Builder::capture_backtrace`], only capturing the backtrace of
+    /// about `rate` (between 0 and 1) of the tracked spans, counted the same
+    /// way as [`Builder::sample_rate`].
+    #[cfg(feature = "backtrace")]
+    pub fn sample_backtraces(mut self, rate: f64) -> Self {
+        self.config.backtrace_rate = Some(rate.clamp(0.0, 1.0));
+        self
+    }
+
+    pub fn build(self) -> SpanDumpLayer {
+        SpanDumpLayer {
+            dispatch_id: OnceLock::new(),
+            dispatch: OnceLock::new(),
+            shared: Arc::new(Shared {
+                threshold_armed: AtomicBool::new(true),
+                high_water: AtomicUsize::new(0),
+                stats: Default::default(),
+                checkpoints: Default::default(),
+                recent: Default::default(),
+                retained: Default::default(),
+                subscribers: Default::default(),
+                name_limits: NameLimits::new(&self.config.name_limits),
+                spans: RwLock::new(Default::default()),
+                postmortem: Mutex::new(VecDeque::with_capacity(self.config.postmortem_capacity)),
+                #[cfg(feature = "hdrhistogram")]
+                histograms: Default::default(),
+                config: self.config,
+            }),
+        }
+    }
+}
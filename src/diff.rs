@@ -0,0 +1,131 @@
+use std::io;
+
+use crate::{SpanDumpError, SpanRecord, SpanSnapshot};
+
+/// The spans that opened and closed between two snapshots, see
+/// [`SpanSnapshot::diff`].
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotDiff {
+    /// Spans open in the later snapshot but not the earlier one, in
+    /// creation order.
+    pub opened: Vec<SpanRecord>,
+    /// Spans open in the earlier snapshot but not the later one, in
+    /// creation order.
+    pub closed: Vec<SpanRecord>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.opened.is_empty() && self.closed.is_empty()
+    }
+
+    /// Writes one `+ opened: name id=..` line per opened span in creation
+    /// order, then one `- closed: id=..` line per closed span ordered by id.
+    pub fn dump_to<W: io::Write>(&self, w: &mut W) -> Result<(), SpanDumpError> {
+        for span in &self.opened {
+            writeln!(w, "+ opened: {} id={}", span.meta.name, span.id.into_u64())?;
+        }
+
+        let mut closed: Vec<u64> = self.closed.iter().map(|span| span.id.into_u64()).collect();
+        closed.sort_unstable();
+        for id in closed {
+            writeln!(w, "- closed: id={}", id)?;
+        }
+        Ok(())
+    }
+}
+
+impl SpanSnapshot {
+    /// Compares this snapshot with a `later` one taken from the same layer.
+    ///
+    /// Spans are matched by id and creation order, so an id that was closed
+    /// and reused in between shows up as both closed and opened.
+    pub fn diff(&self, later: &SpanSnapshot) -> SnapshotDiff {
+        let missing_from = |snapshot: &SpanSnapshot, span: &SpanRecord| {
+            snapshot
+                .get(&span.id)
+                .is_none_or(|other| other.seq != span.seq)
+        };
+
+        SnapshotDiff {
+            opened: later
+                .open_spans_by_creation()
+                .into_iter()
+                .filter(|span| missing_from(self, span))
+                .cloned()
+                .collect(),
+            closed: self
+                .open_spans_by_creation()
+                .into_iter()
+                .filter(|span| missing_from(later, span))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_diff() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _kept = info_span!("kept");
+        let closed = info_span!("closed");
+        let before = span_dumper.snapshot();
+        drop(closed);
+        let _opened = info_span!("opened");
+
+        let diff = before.diff(&span_dumper.snapshot());
+        let names = |spans: &[crate::SpanRecord]| {
+            spans.iter().map(|span| span.meta.name).collect::<Vec<_>>()
+        };
+        assert_eq!(names(&diff.opened), ["opened"]);
+        assert_eq!(names(&diff.closed), ["closed"]);
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn test_dump_to() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let first = info_span!("first");
+        let second = info_span!("second");
+        let before = span_dumper.snapshot();
+        let (first_id, second_id) = (first.id().unwrap(), second.id().unwrap());
+        drop((second, first));
+        let opened = info_span!("opened");
+
+        let mut out = Vec::new();
+        before
+            .diff(&span_dumper.snapshot())
+            .dump_to(&mut out)
+            .unwrap();
+
+        let mut closed = [first_id.into_u64(), second_id.into_u64()];
+        closed.sort_unstable();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "+ opened: opened id={}\n- closed: id={}\n- closed: id={}\n",
+                opened.id().unwrap().into_u64(),
+                closed[0],
+                closed[1]
+            )
+        );
+    }
+}
@@ -0,0 +1,529 @@
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use tracing::span;
+
+use crate::dump::{descendant_count, IdLabels};
+use crate::{DumpOptions, SpanDumpError, SpanDumpLayer, SpanEvent, SpanRecord, SpanSnapshot};
+
+/// Serializable form of a [`SpanRecord`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpanRecordDto {
+    pub id: u64,
+    pub parent: Option<u64>,
+    pub name: String,
+    pub target: String,
+    pub level: String,
+    #[serde(default)]
+    pub module_path: Option<String>,
+    /// Wall-clock open time as milliseconds since the unix epoch.
+    pub created_at_ms: u64,
+    /// Age when the snapshot was taken. Omitted for records serialized on
+    /// their own, e.g. in a [`SpanEventDto`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age_ms: Option<u64>,
+    /// Time spent entered, see [`SpanRecord::busy_duration`]. This and the
+    /// other timing fields are only present for spans entered while
+    /// [`Builder::track_enters`](crate::Builder::track_enters) was enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub busy_ms: Option<u64>,
+    /// Time open but not entered, see [`SpanRecord::idle_duration`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enter_count: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_entered: Option<bool>,
+}
+
+impl SpanRecordDto {
+    /// Builds the DTO for `record`, measuring ages and durations at `now`
+    /// when given.
+    fn new(record: &SpanRecord, ids: &IdLabels<'_>, now: Option<Instant>) -> Self {
+        let timing = now.filter(|_| record.timing.enter_count > 0);
+        SpanRecordDto {
+            id: ids.number(&record.id).unwrap_or_default(),
+            parent: record.parent.as_ref().and_then(|id| ids.number(id)),
+            name: record.meta.name.to_string(),
+            target: record.meta.target.to_string(),
+            level: record.meta.level.to_string(),
+            module_path: record.meta.module_path.map(str::to_string),
+            created_at_ms: unix_millis(record.created_at),
+            age_ms: now.map(|now| millis(record.age_at(now))),
+            busy_ms: timing.map(|now| millis(record.busy_duration_at(now))),
+            idle_ms: timing.map(|now| millis(record.idle_duration_at(now))),
+            enter_count: timing.map(|_| record.timing.enter_count),
+            is_entered: timing.map(|_| record.timing.is_entered()),
+        }
+    }
+}
+
+impl From<&SpanRecord> for SpanRecordDto {
+    fn from(record: &SpanRecord) -> Self {
+        SpanRecordDto::new(record, &IdLabels::raw(), None)
+    }
+}
+
+/// A [`SpanRecordDto`] nested with its children, as produced by
+/// [`SpanSnapshot::to_json_tree`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpanTreeDto {
+    #[serde(flatten)]
+    pub span: SpanRecordDto,
+    pub children: Vec<SpanTreeDto>,
+    /// Descendants left out of `children` because of
+    /// [`DumpOptions::max_depth`].
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub elided: usize,
+}
+
+impl SpanTreeDto {
+    fn build(
+        record: &SpanRecord,
+        index: &HashMap<Option<&span::Id>, Vec<&SpanRecord>>,
+        ids: &IdLabels<'_>,
+        options: &DumpOptions,
+        now: Instant,
+        depth: usize,
+    ) -> Self {
+        let span = SpanRecordDto::new(record, ids, Some(now));
+        if options.max_depth.is_some_and(|max| depth >= max) {
+            return SpanTreeDto {
+                span,
+                children: Vec::new(),
+                elided: descendant_count(index, record),
+            };
+        }
+
+        let children = index
+            .get(&Some(&record.id))
+            .into_iter()
+            .flatten()
+            .map(|child| SpanTreeDto::build(child, index, ids, options, now, depth + 1))
+            .collect();
+
+        SpanTreeDto {
+            span,
+            children,
+            elided: 0,
+        }
+    }
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// Serializable form of a [`SpanEvent`], tagged with a `type` of `opened`
+/// or `closed`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SpanEventDto {
+    Opened(SpanRecordDto),
+    Closed { id: u64 },
+}
+
+impl From<&SpanEvent> for SpanEventDto {
+    fn from(event: &SpanEvent) -> Self {
+        match event {
+            SpanEvent::Opened(record) => SpanEventDto::Opened(SpanRecordDto::from(&**record)),
+            SpanEvent::Closed(id) => SpanEventDto::Closed { id: id.into_u64() },
+        }
+    }
+}
+
+impl SpanEvent {
+    /// Serializes the event as a single line of JSON, e.g. for a WebSocket
+    /// message.
+    pub fn to_json(&self) -> Result<String, SpanDumpError> {
+        Ok(serde_json::to_string(&SpanEventDto::from(self))?)
+    }
+
+    /// Formats the event as a server-sent events frame, a `data:` line
+    /// holding [`SpanEvent::to_json`] followed by a blank line.
+    pub fn to_sse(&self) -> Result<String, SpanDumpError> {
+        Ok(format!("data: {}\n\n", self.to_json()?))
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl SpanSnapshot {
+    /// The open spans in creation order.
+    pub fn to_dto(&self) -> Vec<SpanRecordDto> {
+        self.to_dto_with(&DumpOptions::default())
+    }
+
+    pub fn to_dto_with(&self, options: &DumpOptions) -> Vec<SpanRecordDto> {
+        let ids = IdLabels::new(self, options.stable_ids);
+        let now = self.now();
+        self.open_spans_by_creation()
+            .into_iter()
+            .map(|span| SpanRecordDto::new(span, &ids, Some(now)))
+            .collect()
+    }
+
+    /// Serializes the open spans as a flat JSON list.
+    pub fn to_json(&self) -> Result<String, SpanDumpError> {
+        self.to_json_with(&DumpOptions::default())
+    }
+
+    pub fn to_json_with(&self, options: &DumpOptions) -> Result<String, SpanDumpError> {
+        self.json_with_header(&self.to_dto_with(options), options)
+    }
+
+    pub fn to_dto_tree(&self) -> Vec<SpanTreeDto> {
+        self.to_dto_tree_with(&DumpOptions::default())
+    }
+
+    pub fn to_dto_tree_with(&self, options: &DumpOptions) -> Vec<SpanTreeDto> {
+        let ids = IdLabels::new(self, options.stable_ids);
+        let index = self.children_index();
+        let now = self.now();
+        self.roots()
+            .into_iter()
+            .map(|root| SpanTreeDto::build(root, &index, &ids, options, now, 0))
+            .collect()
+    }
+
+    /// Serializes the open spans as a list of root spans, each nesting its
+    /// children under `children`. Spans whose parent is not open are treated
+    /// as roots.
+    pub fn to_json_tree(&self) -> Result<String, SpanDumpError> {
+        self.to_json_tree_with(&DumpOptions::default())
+    }
+
+    /// Like [`SpanSnapshot::to_json_tree`] with non-default options.
+    pub fn to_json_tree_with(&self, options: &DumpOptions) -> Result<String, SpanDumpError> {
+        self.json_with_header(&self.to_dto_tree_with(options), options)
+    }
+
+    /// Serializes `spans`, wrapped with the [`DumpOptions::header`] fields if
+    /// enabled, and indented with [`DumpOptions::pretty_json`].
+    fn json_with_header<T: Serialize>(
+        &self,
+        spans: &T,
+        options: &DumpOptions,
+    ) -> Result<String, SpanDumpError> {
+        #[derive(Serialize)]
+        struct WithHeader<'a, T> {
+            captured_at: String,
+            count: usize,
+            spans: &'a T,
+        }
+
+        fn to_string<T: Serialize>(value: &T, pretty: bool) -> serde_json::Result<String> {
+            if pretty {
+                serde_json::to_string_pretty(value)
+            } else {
+                serde_json::to_string(value)
+            }
+        }
+
+        if !options.header {
+            return Ok(to_string(spans, options.pretty_json)?);
+        }
+        let with_header = WithHeader {
+            captured_at: self.header_time(),
+            count: self.len(),
+            spans,
+        };
+        Ok(to_string(&with_header, options.pretty_json)?)
+    }
+}
+
+impl SpanDumpLayer {
+    /// Writes the open spans as JSON lines, one [`SpanRecordDto`] per line in
+    /// creation order, without holding the layer's lock while serializing.
+    ///
+    /// The open ids are collected under a brief read lock, then each record
+    /// is looked up again under its own brief lock, so span creation is only
+    /// ever blocked for one record at a time. Spans opened after the call
+    /// started are not written, and spans that close part way through are
+    /// skipped, so the output is not a single consistent view. Ids are the
+    /// raw span ids.
+    pub fn stream_ndjson<W: io::Write>(&self, w: &mut W) -> Result<(), SpanDumpError> {
+        let now = self.shared.config.now();
+        let mut ids: Vec<(u64, span::Id)> = {
+            let spans_read = self.shared.read_spans();
+            spans_read
+                .open_spans()
+                .map(|span| (span.seq, span.id.clone()))
+                .collect()
+        };
+        ids.sort_unstable_by_key(|(seq, _)| *seq);
+
+        let raw = IdLabels::raw();
+        for (seq, id) in ids {
+            let dto = {
+                let spans_read = self.shared.read_spans();
+                spans_read
+                    .get(&id)
+                    .filter(|span| span.seq == seq)
+                    .map(|span| SpanRecordDto::new(span, &raw, Some(now)))
+            };
+            if let Some(dto) = dto {
+                serde_json::to_writer(&mut *w, &dto)?;
+                writeln!(w)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{info_span, Span};
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_created_at_unix_millis() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let before = unix_millis(SystemTime::now());
+        let _s = info_span!("test").entered();
+        let after = unix_millis(SystemTime::now());
+
+        let dto = span_dumper.snapshot().to_dto();
+        assert_eq!(dto.len(), 1);
+        assert_eq!(dto[0].name, "test");
+        assert_eq!(dto[0].module_path.as_deref(), Some(module_path!()));
+        assert!(dto[0].created_at_ms >= before && dto[0].created_at_ms <= after);
+
+        let json = span_dumper.snapshot().to_json().unwrap();
+        let parsed: Vec<SpanRecordDto> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, dto);
+    }
+
+    #[test]
+    fn test_timing_fields() {
+        let span_dumper = SpanDumpLayer::builder().track_enters(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let span = info_span!("timed");
+        span.in_scope(|| {});
+        let _entered = span.enter();
+        let _never_entered = info_span!("never_entered");
+
+        let snapshot = span_dumper.snapshot();
+        let dto = snapshot.to_dto();
+        assert_eq!(dto[0].enter_count, Some(2));
+        assert_eq!(dto[0].is_entered, Some(true));
+        assert!(dto[0].busy_ms.is_some() && dto[0].idle_ms.is_some());
+        assert!(dto[0].age_ms.is_some());
+        assert_eq!(dto[1].enter_count, None);
+
+        let json = snapshot.to_json().unwrap();
+        let parsed: Vec<SpanRecordDto> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, dto);
+        assert_eq!(json.matches("enter_count").count(), 1);
+        let record = snapshot.get(&span.id().unwrap()).unwrap();
+        assert_eq!(SpanRecordDto::from(record).age_ms, None);
+    }
+
+    #[test]
+    fn test_json_tree() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let child_a = info_span!(parent: &root, "child_a");
+        let _grandchild = info_span!(parent: &child_a, "grandchild");
+        let _child_b = info_span!(parent: &root, "child_b");
+        let missing_parent = info_span!("missing_parent");
+        let _orphan = info_span!(parent: &missing_parent, "orphan");
+        let _detached = info_span!(parent: Span::none(), "detached");
+
+        let mut snapshot = span_dumper.snapshot();
+        snapshot.close_span(missing_parent.id().unwrap());
+
+        let json = snapshot.to_json_tree().unwrap();
+        let tree: Vec<SpanTreeDto> = serde_json::from_str(&json).unwrap();
+
+        fn names(nodes: &[SpanTreeDto]) -> Vec<&str> {
+            nodes.iter().map(|node| node.span.name.as_str()).collect()
+        }
+
+        assert_eq!(names(&tree), ["root", "orphan", "detached"]);
+        assert_eq!(names(&tree[0].children), ["child_a", "child_b"]);
+        assert_eq!(names(&tree[0].children[0].children), ["grandchild"]);
+        assert!(tree[1].children.is_empty());
+    }
+
+    #[test]
+    fn test_span_event_frames() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let events = span_dumper.subscribe();
+        let span = info_span!("request");
+        let id = span.id().unwrap().into_u64();
+        drop(span);
+
+        let opened = events.try_recv().unwrap().to_json().unwrap();
+        match serde_json::from_str(&opened).unwrap() {
+            SpanEventDto::Opened(dto) => assert_eq!(dto.name, "request"),
+            dto => panic!("unexpected {:?}", dto),
+        }
+        assert!(opened.starts_with(r#"{"type":"opened","#));
+        assert_eq!(
+            events.try_recv().unwrap().to_sse().unwrap(),
+            format!("data: {{\"type\":\"closed\",\"id\":{}}}\n\n", id)
+        );
+    }
+
+    #[test]
+    fn test_json_tree_max_depth() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let child = info_span!(parent: &root, "child");
+        let _grandchild = info_span!(parent: &child, "grandchild");
+
+        let options = DumpOptions {
+            stable_ids: true,
+            max_depth: Some(0),
+            ..Default::default()
+        };
+        let json = span_dumper.snapshot().to_json_tree_with(&options).unwrap();
+        let tree: Vec<SpanTreeDto> = serde_json::from_str(&json).unwrap();
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].span.id, 1);
+        assert!(tree[0].children.is_empty());
+        assert_eq!(tree[0].elided, 2);
+        assert!(!span_dumper
+            .snapshot()
+            .to_json_tree()
+            .unwrap()
+            .contains("elided"));
+    }
+
+    #[test]
+    fn test_json_header() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _root = info_span!("root");
+        let options = DumpOptions {
+            header: true,
+            ..Default::default()
+        };
+        let json = span_dumper.snapshot().to_json_with(&options).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["count"], 1);
+        assert!(value["captured_at"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(value["spans"][0]["name"], "root");
+    }
+
+    #[test]
+    fn test_pretty_json() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let _child = info_span!(parent: &root, "child");
+
+        let snapshot = span_dumper.snapshot();
+        let options = DumpOptions {
+            pretty_json: true,
+            ..Default::default()
+        };
+        let pretty = snapshot.to_json_tree_with(&options).unwrap();
+        assert!(pretty.contains("\n  {\n    \"id\""));
+        let parsed: Vec<SpanTreeDto> = serde_json::from_str(&pretty).unwrap();
+        assert_eq!(parsed, snapshot.to_dto_tree());
+        assert!(!snapshot.to_json_tree().unwrap().contains('\n'));
+    }
+
+    #[test]
+    fn test_json_stable_ids() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let _child = info_span!(parent: &root, "child");
+
+        let options = DumpOptions {
+            stable_ids: true,
+            ..Default::default()
+        };
+        let dto = span_dumper.snapshot().to_dto_with(&options);
+        assert_eq!(
+            dto.iter()
+                .map(|span| (span.id, span.parent))
+                .collect::<Vec<_>>(),
+            [(1, None), (2, Some(1))]
+        );
+    }
+
+    #[test]
+    fn test_stream_ndjson() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let first = info_span!("first");
+        let _second = info_span!(parent: &first, "second");
+
+        let mut out = Vec::new();
+        span_dumper.stream_ndjson(&mut out).unwrap();
+        let lines: Vec<SpanRecordDto> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(
+            lines
+                .iter()
+                .map(|dto| dto.name.as_str())
+                .collect::<Vec<_>>(),
+            ["first", "second"]
+        );
+        assert_eq!(lines[1].parent, Some(first.id().unwrap().into_u64()));
+        assert!(lines[0].age_ms.is_some());
+    }
+}
@@ -0,0 +1,1237 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::span;
+
+use crate::{FilterDecision, ParentKind, SpanDumpError, SpanDumpLayer, SpanRecord, SpanSnapshot};
+
+impl SpanSnapshot {
+    /// Renders one line per open span, each truncated with an ellipsis to
+    /// fit within `width` characters, for display in terminal UIs.
+    pub fn to_frame(&self, width: usize) -> Vec<String> {
+        self.open_spans_by_creation()
+            .into_iter()
+            .map(|span| {
+                let line = format!(
+                    "{} {} ({}) {:?}",
+                    span.meta.level,
+                    span.meta.name,
+                    span.meta.target,
+                    self.age_of(span)
+                );
+                truncate_line(&line, width)
+            })
+            .collect()
+    }
+
+    /// Writes the open spans as an indented tree, one span per line. Spans
+    /// whose parent is not open are printed as roots.
+    pub fn dump_tree_to<W: io::Write>(
+        &self,
+        w: &mut W,
+        style: &TreeStyle,
+    ) -> Result<(), SpanDumpError> {
+        let options = DumpOptions {
+            tree_style: style.clone(),
+            ..Default::default()
+        };
+        self.dump_tree_with(w, &options)
+    }
+
+    /// Writes every open span in creation order with all of its captured
+    /// details, one indented property per line.
+    pub fn dump_verbose_to<W: io::Write>(&self, w: &mut W) -> Result<(), SpanDumpError> {
+        self.dump_verbose_with(w, &DumpOptions::default())
+    }
+
+    /// Writes one line per open span in creation order, with any captured
+    /// fields after the name as in [`SpanRecord::format_fields`].
+    pub fn dump_text_to<W: io::Write>(&self, w: &mut W) -> Result<(), SpanDumpError> {
+        self.dump_text_with(w, &DumpOptions::default())
+    }
+
+    /// Prints the text dump to stdout.
+    pub fn dump_text(&self) {
+        let _ = self.dump_text_to(&mut io::stdout().lock());
+    }
+
+    /// Renders the open span trees in the folded stack format read by
+    /// `flamegraph.pl` and `inferno`: one `root;child;leaf count` line per
+    /// distinct stack ending in a span without open children, sorted.
+    ///
+    /// Each leaf counts 1, unless some span in the snapshot was entered with
+    /// [`Builder::track_enters`](crate::Builder::track_enters) enabled, in
+    /// which case leaves weigh their busy time in milliseconds. Semicolons
+    /// in span names are replaced with `:` to keep the stacks unambiguous.
+    pub fn to_folded_stacks(&self) -> String {
+        let now = self.now();
+        let timed = self.any(|span| span.timing.enter_count > 0);
+        let index = self.children_index();
+
+        let mut stacks: BTreeMap<String, u128> = BTreeMap::new();
+        for span in self.open_spans() {
+            if index.contains_key(&Some(&span.id)) {
+                continue;
+            }
+            let frames: Vec<_> = self
+                .ancestors(&span.id)
+                .into_iter()
+                .rev()
+                .chain([span])
+                .map(|span| span.meta.name.replace(';', ":"))
+                .collect();
+            let weight = if timed {
+                span.busy_duration_at(now).as_millis()
+            } else {
+                1
+            };
+            *stacks.entry(frames.join(";")).or_default() += weight;
+        }
+
+        stacks
+            .into_iter()
+            .map(|(stack, weight)| format!("{stack} {weight}\n"))
+            .collect()
+    }
+
+    /// Writes the span `id` as a small tree: its open ancestors from the
+    /// root down, the span itself marked with `>`, and its direct children.
+    /// Writes a `not found` line instead if `id` is not open.
+    pub fn dump_context_to<W: io::Write>(
+        &self,
+        id: &span::Id,
+        w: &mut W,
+    ) -> Result<(), SpanDumpError> {
+        let Some(span) = self.get(id) else {
+            writeln!(w, "span id={} not found", id.into_u64())?;
+            return Ok(());
+        };
+
+        let ids = IdLabels::new(self, false);
+        let mut depth = 0;
+        for ancestor in self.ancestors(id).into_iter().rev() {
+            writeln!(
+                w,
+                "{:indent$}{}",
+                "",
+                tree_label(ancestor, &ids),
+                indent = depth * 2
+            )?;
+            depth += 1;
+        }
+        writeln!(
+            w,
+            "{:indent$}> {}",
+            "",
+            tree_label(span, &ids),
+            indent = depth * 2
+        )?;
+        for child in self.open_spans_by_creation() {
+            if child.parent.as_ref() == Some(id) {
+                writeln!(
+                    w,
+                    "{:indent$}{}",
+                    "",
+                    tree_label(child, &ids),
+                    indent = (depth + 1) * 2
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn dump_to<W: io::Write>(
+        &self,
+        w: &mut W,
+        format: DumpFormat,
+    ) -> Result<(), SpanDumpError> {
+        self.dump_to_with(w, format, &DumpOptions::default())
+    }
+
+    /// Like [`SpanSnapshot::dump_to`] with non-default rendering options.
+    pub fn dump_to_with<W: io::Write>(
+        &self,
+        w: &mut W,
+        format: DumpFormat,
+        options: &DumpOptions,
+    ) -> Result<(), SpanDumpError> {
+        match format {
+            DumpFormat::Text => self.dump_text_with(w, options),
+            DumpFormat::Tree => self.dump_tree_with(w, options),
+            DumpFormat::Verbose => self.dump_verbose_with(w, options),
+            #[cfg(feature = "serde")]
+            DumpFormat::Json => Ok(writeln!(w, "{}", self.to_json_with(options)?)?),
+        }
+    }
+
+    /// Writes the given format to the file at `path`, creating it or
+    /// replacing its contents.
+    pub fn dump_to_file(
+        &self,
+        path: impl AsRef<Path>,
+        format: DumpFormat,
+    ) -> Result<(), SpanDumpError> {
+        self.dump_to_file_with(File::create(path)?, format)
+    }
+
+    /// Like [`SpanSnapshot::dump_to_file`], appending to the file instead of
+    /// replacing its contents.
+    pub fn append_to_file(
+        &self,
+        path: impl AsRef<Path>,
+        format: DumpFormat,
+    ) -> Result<(), SpanDumpError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.dump_to_file_with(file, format)
+    }
+
+    fn dump_to_file_with(&self, file: File, format: DumpFormat) -> Result<(), SpanDumpError> {
+        let mut w = io::BufWriter::new(file);
+        self.dump_to(&mut w, format)?;
+        Ok(w.flush()?)
+    }
+
+    /// The capture time to report in dump headers, now for snapshots not
+    /// taken from a layer.
+    pub(crate) fn header_time(&self) -> String {
+        rfc3339(self.captured_at().unwrap_or_else(SystemTime::now))
+    }
+
+    fn dump_header<W: io::Write>(
+        &self,
+        w: &mut W,
+        options: &DumpOptions,
+    ) -> Result<(), SpanDumpError> {
+        if options.header {
+            writeln!(
+                w,
+                "# captured_at={} count={}",
+                self.header_time(),
+                self.len()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the [`DumpOptions::empty_message`] if no spans are open,
+    /// returning whether the snapshot was empty.
+    fn dump_if_empty<W: io::Write>(
+        &self,
+        w: &mut W,
+        options: &DumpOptions,
+    ) -> Result<bool, SpanDumpError> {
+        if !self.is_empty() {
+            return Ok(false);
+        }
+        if let Some(message) = &options.empty_message {
+            writeln!(w, "{message}")?;
+        }
+        Ok(true)
+    }
+
+    fn dump_tree_with<W: io::Write>(
+        &self,
+        w: &mut W,
+        options: &DumpOptions,
+    ) -> Result<(), SpanDumpError> {
+        self.dump_header(w, options)?;
+        if self.dump_if_empty(w, options)? {
+            return Ok(());
+        }
+        let ids = IdLabels::new(self, options.stable_ids);
+        let index = self.children_index();
+        let tree = TreeDump {
+            options,
+            ids: &ids,
+            index: &index,
+        };
+        let (orphans, roots): (Vec<_>, Vec<_>) = self
+            .roots()
+            .into_iter()
+            .partition(|span| options.group_orphans_under_synthetic_root && span.parent.is_some());
+        for root in roots {
+            writeln!(w, "{}", tree_label(root, &ids))?;
+            tree.children(w, root, 0, &mut String::new())?;
+        }
+        if !orphans.is_empty() {
+            writeln!(w, "{ORPHANS_LABEL} ({})", orphans.len())?;
+            tree.siblings(w, &orphans, 0, &mut String::new(), || {
+                orphans
+                    .iter()
+                    .map(|span| 1 + descendant_count(&index, span))
+                    .sum()
+            })?;
+        }
+        Ok(())
+    }
+
+    fn dump_verbose_with<W: io::Write>(
+        &self,
+        w: &mut W,
+        options: &DumpOptions,
+    ) -> Result<(), SpanDumpError> {
+        self.dump_header(w, options)?;
+        if self.dump_if_empty(w, options)? {
+            return Ok(());
+        }
+        let ids = IdLabels::new(self, options.stable_ids);
+        for span in self.open_spans_by_creation() {
+            writeln!(w, "{} id={}", span.meta.name, ids.label(&span.id))?;
+            writeln!(w, "  target: {}", span.meta.target)?;
+            if let Some(module_path) = span.meta.module_path {
+                writeln!(w, "  module: {}", module_path)?;
+            }
+            writeln!(w, "  level: {}", span.meta.level)?;
+            match &span.parent_kind {
+                ParentKind::Explicit(id) => writeln!(w, "  parent: explicit id={}", ids.label(id))?,
+                ParentKind::Contextual(id) => {
+                    writeln!(w, "  parent: contextual id={}", ids.label(id))?
+                }
+                ParentKind::Root => writeln!(w, "  parent: root")?,
+            }
+            match span.filter {
+                FilterDecision::Unfiltered => {}
+                FilterDecision::Passed => writeln!(w, "  filter: passed")?,
+                FilterDecision::Rejected => writeln!(w, "  filter: rejected")?,
+            }
+            writeln!(w, "  age: {:?}", self.age_of(span))?;
+            if span.timing.enter_count > 0 {
+                let now = self.now();
+                writeln!(
+                    w,
+                    "  busy: {:?} idle: {:?} enters: {}",
+                    span.busy_duration_at(now),
+                    span.idle_duration_at(now),
+                    span.timing.enter_count
+                )?;
+            }
+            #[cfg(feature = "tokio-task-id")]
+            if let Some(task_id) = span.task_id {
+                writeln!(w, "  task: {}", task_id)?;
+            }
+            #[cfg(feature = "backtrace")]
+            if let Some(backtrace) = &span.backtrace {
+                writeln!(w, "  backtrace:")?;
+                for line in backtrace.to_string().lines() {
+                    writeln!(w, "    {}", line)?;
+                }
+            }
+            if let Some(dispatch_id) = span.dispatch_id {
+                writeln!(w, "  dispatch: {}", dispatch_id)?;
+            }
+            for (name, value) in span.fields.iter() {
+                writeln!(w, "  field {}: {}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn dump_text_with<W: io::Write>(
+        &self,
+        w: &mut W,
+        options: &DumpOptions,
+    ) -> Result<(), SpanDumpError> {
+        self.dump_header(w, options)?;
+        if self.dump_if_empty(w, options)? {
+            return Ok(());
+        }
+        let ids = IdLabels::new(self, options.stable_ids);
+        for span in self.open_spans_by_creation() {
+            let fields = if options.empty_fields || !span.fields.is_empty() {
+                span.format_fields()
+            } else {
+                String::new()
+            };
+            writeln!(
+                w,
+                "{} {}{} ({}) id={} age={:?}",
+                span.meta.level,
+                span.meta.name,
+                fields,
+                span.meta.target,
+                ids.label(&span.id),
+                self.age_of(span)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with millisecond precision,
+/// e.g. `2024-05-01T12:30:00.250Z`.
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Converts days since the epoch to a civil date, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Rendering options shared by the dump formats, see
+/// [`SpanSnapshot::dump_to_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpOptions {
+    /// Connectors for [`DumpFormat::Tree`].
+    pub tree_style: TreeStyle,
+    /// Replace span ids with small sequence numbers (`#1`, `#2`, ...)
+    /// assigned in creation order for this dump only, so output stays the
+    /// same across runs. Parent references are mapped consistently; parents
+    /// that are not part of the dump render as `#?` (or are omitted in
+    /// JSON).
+    pub stable_ids: bool,
+    /// The deepest level printed by the tree formats, roots being level 0.
+    /// The descendants of spans at this level are summarized as a single
+    /// `... (N more)` line, or as `elided` in JSON.
+    pub max_depth: Option<usize>,
+    /// In [`DumpFormat::Tree`], group children of one parent that share a
+    /// name under a single `name (xN)` line, listing only the first three
+    /// followed by `... (M more)`. Keeps spans that fan out into many
+    /// per-item children readable.
+    pub collapse_identical_siblings: bool,
+    /// In [`DumpFormat::Tree`], print the spans whose parent is not open
+    /// under a single synthetic `<orphans> (N)` root after the real roots,
+    /// rather than each as a root of its own. Declutters dumps of programs
+    /// whose parents routinely close before their children.
+    pub group_orphans_under_synthetic_root: bool,
+    /// Start the dump with a `# captured_at=.. count=..` line holding the
+    /// [`SpanSnapshot::captured_at`] time in RFC 3339 and the open span
+    /// count. JSON dumps become an object with `captured_at`, `count` and
+    /// the usual list under `spans`. Off by default, so dumps of the same
+    /// spans are identical.
+    pub header: bool,
+    /// Indent JSON dumps over multiple lines for reading by hand, instead of
+    /// the compact single line.
+    pub pretty_json: bool,
+    /// Write `{}` after spans without captured fields in
+    /// [`DumpFormat::Text`], rather than nothing.
+    pub empty_fields: bool,
+    /// The line written by the text, tree and verbose formats when no spans
+    /// are open, after the header if enabled. `None` writes nothing else, so
+    /// the dump is empty or only the header. Defaults to
+    /// [`NO_OPEN_SPANS`]; JSON dumps are always an empty list.
+    pub empty_message: Option<String>,
+}
+
+/// The default [`DumpOptions::empty_message`].
+pub const NO_OPEN_SPANS: &str = "no open spans";
+
+impl Default for DumpOptions {
+    fn default() -> Self {
+        DumpOptions {
+            tree_style: TreeStyle::default(),
+            stable_ids: false,
+            max_depth: None,
+            collapse_identical_siblings: false,
+            group_orphans_under_synthetic_root: false,
+            header: false,
+            pretty_json: false,
+            empty_fields: false,
+            empty_message: Some(NO_OPEN_SPANS.to_string()),
+        }
+    }
+}
+
+/// Maps span ids to the labels printed for them.
+pub(crate) struct IdLabels<'a> {
+    stable: Option<HashMap<&'a span::Id, u64>>,
+}
+
+impl<'a> IdLabels<'a> {
+    pub(crate) fn new(snapshot: &'a SpanSnapshot, stable: bool) -> Self {
+        let stable = stable.then(|| {
+            snapshot
+                .open_spans_by_creation()
+                .into_iter()
+                .zip(1..)
+                .map(|(span, n)| (&span.id, n))
+                .collect()
+        });
+        IdLabels { stable }
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn raw() -> Self {
+        IdLabels { stable: None }
+    }
+
+    /// The number used for `id`, or `None` for a stable id outside the dump.
+    pub(crate) fn number(&self, id: &span::Id) -> Option<u64> {
+        match &self.stable {
+            Some(stable) => stable.get(id).copied(),
+            None => Some(id.into_u64()),
+        }
+    }
+
+    fn label(&self, id: &span::Id) -> String {
+        match (&self.stable, self.number(id)) {
+            (Some(_), Some(n)) => format!("#{}", n),
+            (Some(_), None) => "#?".to_string(),
+            (None, _) => id.into_u64().to_string(),
+        }
+    }
+}
+
+/// The output formats accepted by [`SpanSnapshot::dump_to`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// [`SpanSnapshot::dump_text_to`]
+    Text,
+    /// [`SpanSnapshot::dump_tree_to`] with the default style.
+    Tree,
+    /// [`SpanSnapshot::dump_verbose_to`]
+    Verbose,
+    /// [`SpanSnapshot::to_json`] followed by a newline.
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+/// Dumps the layer's open spans when dropped, see
+/// [`SpanDumpLayer::dump_on_drop`].
+pub struct DumpOnDrop<W: io::Write = io::Stderr> {
+    layer: SpanDumpLayer,
+    format: DumpFormat,
+    writer: W,
+}
+
+impl<W: io::Write> DumpOnDrop<W> {
+    pub(crate) fn new(layer: SpanDumpLayer, format: DumpFormat, writer: W) -> Self {
+        DumpOnDrop {
+            layer,
+            format,
+            writer,
+        }
+    }
+}
+
+impl<W: io::Write> Drop for DumpOnDrop<W> {
+    fn drop(&mut self) {
+        let _ = self.layer.snapshot().dump_to(&mut self.writer, self.format);
+    }
+}
+
+/// Connectors used by [`SpanSnapshot::dump_tree_to`].
+///
+/// Each line is prefixed with one segment per ancestor level (`vertical` if
+/// that ancestor has later siblings, `indent` otherwise), followed by
+/// `branch`, or `last_branch` for the last child of its parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeStyle {
+    pub indent: String,
+    pub branch: String,
+    pub last_branch: String,
+    pub vertical: String,
+}
+
+impl TreeStyle {
+    /// Box-drawing connectors, e.g. `├── ` and `└── `.
+    pub fn unicode() -> Self {
+        TreeStyle {
+            indent: "    ".to_string(),
+            branch: "├── ".to_string(),
+            last_branch: "└── ".to_string(),
+            vertical: "│   ".to_string(),
+        }
+    }
+}
+
+impl Default for TreeStyle {
+    /// Plain two-space indentation per level.
+    fn default() -> Self {
+        TreeStyle {
+            indent: "  ".to_string(),
+            branch: "  ".to_string(),
+            last_branch: "  ".to_string(),
+            vertical: "  ".to_string(),
+        }
+    }
+}
+
+fn tree_label(span: &SpanRecord, ids: &IdLabels<'_>) -> String {
+    format!(
+        "{} ({}) id={}{}",
+        span.meta.name,
+        span.meta.target,
+        ids.label(&span.id),
+        if span.closed_at.is_some() {
+            " closed"
+        } else {
+            ""
+        }
+    )
+}
+
+/// The label of the synthetic root of [`DumpOptions::group_orphans_under_synthetic_root`].
+const ORPHANS_LABEL: &str = "<orphans>";
+
+/// How many spans of a collapsed group are still printed, see
+/// [`DumpOptions::collapse_identical_siblings`].
+const COLLAPSED_SHOWN: usize = 3;
+
+/// The state shared while writing one tree dump.
+struct TreeDump<'a> {
+    options: &'a DumpOptions,
+    ids: &'a IdLabels<'a>,
+    index: &'a HashMap<Option<&'a span::Id>, Vec<&'a SpanRecord>>,
+}
+
+impl TreeDump<'_> {
+    fn connector(&self, last: bool) -> &str {
+        let style = &self.options.tree_style;
+        if last {
+            &style.last_branch
+        } else {
+            &style.branch
+        }
+    }
+
+    fn continuation(&self, last: bool) -> &str {
+        let style = &self.options.tree_style;
+        if last {
+            &style.indent
+        } else {
+            &style.vertical
+        }
+    }
+
+    fn children<W: io::Write>(
+        &self,
+        w: &mut W,
+        parent: &SpanRecord,
+        depth: usize,
+        prefix: &mut String,
+    ) -> Result<(), SpanDumpError> {
+        let children = self
+            .index
+            .get(&Some(&parent.id))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        self.siblings(w, children, depth, prefix, || {
+            descendant_count(self.index, parent)
+        })
+    }
+
+    /// Writes the spans of one level below a parent at `depth`, or an
+    /// `... (N more)` line with the `elided` count past the maximum depth.
+    fn siblings<W: io::Write>(
+        &self,
+        w: &mut W,
+        children: &[&SpanRecord],
+        depth: usize,
+        prefix: &mut String,
+        elided: impl FnOnce() -> usize,
+    ) -> Result<(), SpanDumpError> {
+        if self.options.max_depth.is_some_and(|max| depth >= max) {
+            if !children.is_empty() {
+                writeln!(
+                    w,
+                    "{}{}... ({} more)",
+                    prefix,
+                    self.connector(true),
+                    elided()
+                )?;
+            }
+            return Ok(());
+        }
+
+        if !self.options.collapse_identical_siblings {
+            for (i, child) in children.iter().enumerate() {
+                self.entry(w, child, depth, prefix, i + 1 == children.len())?;
+            }
+            return Ok(());
+        }
+
+        let mut groups: Vec<Vec<&SpanRecord>> = Vec::new();
+        for &child in children {
+            match groups
+                .iter_mut()
+                .find(|group| group[0].meta.name == child.meta.name)
+            {
+                Some(group) => group.push(child),
+                None => groups.push(vec![child]),
+            }
+        }
+        for (i, group) in groups.iter().enumerate() {
+            let last = i + 1 == groups.len();
+            if let [span] = group.as_slice() {
+                self.entry(w, span, depth, prefix, last)?;
+                continue;
+            }
+
+            let name = group[0].meta.name;
+            writeln!(
+                w,
+                "{}{}{} (x{})",
+                prefix,
+                self.connector(last),
+                name,
+                group.len()
+            )?;
+            let len = prefix.len();
+            prefix.push_str(self.continuation(last));
+            let shown = &group[..group.len().min(COLLAPSED_SHOWN)];
+            let rest = group.len() - shown.len();
+            for (j, span) in shown.iter().enumerate() {
+                self.entry(w, span, depth, prefix, rest == 0 && j + 1 == shown.len())?;
+            }
+            if rest > 0 {
+                writeln!(w, "{}{}... ({} more)", prefix, self.connector(true), rest)?;
+            }
+            prefix.truncate(len);
+        }
+        Ok(())
+    }
+
+    /// Writes `span` and its subtree.
+    fn entry<W: io::Write>(
+        &self,
+        w: &mut W,
+        span: &SpanRecord,
+        depth: usize,
+        prefix: &mut String,
+        last: bool,
+    ) -> Result<(), SpanDumpError> {
+        writeln!(
+            w,
+            "{}{}{}",
+            prefix,
+            self.connector(last),
+            tree_label(span, self.ids)
+        )?;
+
+        let len = prefix.len();
+        prefix.push_str(self.continuation(last));
+        self.children(w, span, depth + 1, prefix)?;
+        prefix.truncate(len);
+        Ok(())
+    }
+}
+
+/// The number of open spans below `span` in the tree.
+pub(crate) fn descendant_count(
+    index: &HashMap<Option<&span::Id>, Vec<&SpanRecord>>,
+    span: &SpanRecord,
+) -> usize {
+    index
+        .get(&Some(&span.id))
+        .into_iter()
+        .flatten()
+        .map(|child| 1 + descendant_count(index, child))
+        .sum()
+}
+
+fn truncate_line(line: &str, width: usize) -> String {
+    if line.chars().count() <= width {
+        return line.to_string();
+    }
+
+    if width == 0 {
+        return String::new();
+    }
+
+    let mut truncated: String = line.chars().take(width - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_dump_tree_styles() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!(target: "app", "root");
+        let child_a = info_span!(target: "app", parent: &root, "child_a");
+        let grandchild = info_span!(target: "app", parent: &child_a, "grandchild");
+        let child_b = info_span!(target: "app", parent: &root, "child_b");
+        let [root, child_a, grandchild, child_b] =
+            [&root, &child_a, &grandchild, &child_b].map(|s| s.id().unwrap().into_u64());
+
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(snapshot.validate(), []);
+
+        let mut plain = Vec::new();
+        snapshot
+            .dump_tree_to(&mut plain, &TreeStyle::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(plain).unwrap(),
+            format!(
+                "root (app) id={root}\n  child_a (app) id={child_a}\n    grandchild (app) id={grandchild}\n  child_b (app) id={child_b}\n"
+            )
+        );
+
+        let mut unicode = Vec::new();
+        snapshot
+            .dump_tree_to(&mut unicode, &TreeStyle::unicode())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(unicode).unwrap(),
+            format!(
+                "root (app) id={root}\n├── child_a (app) id={child_a}\n│   └── grandchild (app) id={grandchild}\n└── child_b (app) id={child_b}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_dump_verbose() {
+        let span_dumper = SpanDumpLayer::builder().capture_fields(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let outer = info_span!("outer").entered();
+        let inner = info_span!("inner", user = "alice");
+        let outer_id = outer.id().unwrap().into_u64();
+        let inner_id = inner.id().unwrap().into_u64();
+
+        let mut out = Vec::new();
+        span_dumper.snapshot().dump_verbose_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with(&format!(
+            "outer id={outer_id}\n  target: {0}\n  module: {0}\n  level: INFO\n  parent: root\n",
+            module_path!()
+        )));
+        assert!(out.contains(&format!(
+            "inner id={inner_id}\n  target: {0}\n  module: {0}\n  level: INFO\n  parent: contextual id={outer_id}\n",
+            module_path!()
+        )));
+        assert!(out.ends_with("  field user: alice\n"));
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[test]
+    fn test_capture_backtrace() {
+        let span_dumper = SpanDumpLayer::builder().sample_backtraces(0.5).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let skipped = info_span!("skipped");
+        let captured = info_span!("captured");
+
+        let snapshot = span_dumper.snapshot();
+        let backtrace = |span: &tracing::Span| {
+            snapshot
+                .get(&span.id().unwrap())
+                .unwrap()
+                .backtrace
+                .is_some()
+        };
+        assert!(!backtrace(&skipped));
+        assert!(backtrace(&captured));
+
+        let mut out = Vec::new();
+        snapshot.dump_verbose_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("  backtrace:\n").count(), 1);
+        assert!(out.contains("test_capture_backtrace"));
+    }
+
+    #[test]
+    fn test_dump_on_drop_captures_at_drop() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _before = info_span!("before").entered();
+        let mut out = Vec::new();
+        let _during;
+        {
+            let _guard = span_dumper.dump_on_drop_to(DumpFormat::Text, &mut out);
+            _during = info_span!("during").entered();
+            let _ = info_span!("closed_early").entered();
+        }
+
+        let out = String::from_utf8(out).unwrap();
+        let names = out
+            .lines()
+            .map(|line| line.split(' ').nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["before", "during"]);
+    }
+
+    #[test]
+    fn test_dump_to_file() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _s = info_span!("to_file").entered();
+        let snapshot = span_dumper.snapshot();
+
+        let path = std::env::temp_dir().join(format!("span-dump-{}.txt", std::process::id()));
+        snapshot.dump_to_file(&path, DumpFormat::Tree).unwrap();
+        snapshot.dump_to_file(&path, DumpFormat::Tree).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        snapshot.append_to_file(&path, DumpFormat::Tree).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents.starts_with("to_file "));
+    }
+
+    #[test]
+    fn test_stable_ids() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let missing = info_span!(target: "app", "missing");
+        let root = info_span!(target: "app", "root");
+        let _child = info_span!(target: "app", parent: &root, "child");
+        let _orphan = info_span!(target: "app", parent: &missing, "orphan");
+
+        let mut snapshot = span_dumper.snapshot();
+        snapshot.close_span(missing.id().unwrap());
+
+        let options = DumpOptions {
+            stable_ids: true,
+            ..Default::default()
+        };
+        let dump = |format| {
+            let mut out = Vec::new();
+            snapshot.dump_to_with(&mut out, format, &options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            dump(DumpFormat::Tree),
+            "root (app) id=#1\n  child (app) id=#2\norphan (app) id=#3\n"
+        );
+        assert!(dump(DumpFormat::Verbose)
+            .contains("orphan id=#3\n  target: app\n  module: tracing_span_dump::dump::tests\n  level: INFO\n  parent: explicit id=#?\n"));
+    }
+
+    #[test]
+    fn test_group_orphans() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _root = info_span!(target: "app", "root");
+        let gone = info_span!(target: "app", "gone");
+        let first = info_span!(target: "app", parent: &gone, "first");
+        let _nested = info_span!(target: "app", parent: &first, "nested");
+        let _second = info_span!(target: "app", parent: &gone, "second");
+
+        let mut snapshot = span_dumper.snapshot();
+        snapshot.close_span(gone.id().unwrap());
+        let options = DumpOptions {
+            stable_ids: true,
+            group_orphans_under_synthetic_root: true,
+            ..Default::default()
+        };
+        let dump = |options: &DumpOptions| {
+            let mut out = Vec::new();
+            snapshot
+                .dump_to_with(&mut out, DumpFormat::Tree, options)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            dump(&options),
+            "root (app) id=#1\n<orphans> (2)\n  first (app) id=#2\n    nested (app) id=#3\n  second (app) id=#4\n"
+        );
+
+        let options = DumpOptions {
+            max_depth: Some(0),
+            ..options
+        };
+        assert_eq!(
+            dump(&options),
+            "root (app) id=#1\n<orphans> (2)\n  ... (3 more)\n"
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!(target: "app", "root");
+        let child = info_span!(target: "app", parent: &root, "child");
+        let grandchild = info_span!(target: "app", parent: &child, "grandchild");
+        let _leaf = info_span!(target: "app", parent: &grandchild, "leaf");
+        let _sibling = info_span!(target: "app", parent: &child, "sibling");
+
+        let options = DumpOptions {
+            stable_ids: true,
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        span_dumper
+            .snapshot()
+            .dump_to_with(&mut out, DumpFormat::Tree, &options)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "root (app) id=#1\n  child (app) id=#2\n    ... (3 more)\n"
+        );
+    }
+
+    #[test]
+    fn test_to_frame_truncates() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _short = info_span!("short").entered();
+        let _long = info_span!("a_very_long_span_name_that_does_not_fit").entered();
+
+        let frame = span_dumper.snapshot().to_frame(20);
+        assert_eq!(frame.len(), 2);
+        assert!(frame.iter().all(|line| line.chars().count() <= 20));
+        assert!(frame[1].ends_with('…'));
+
+        assert_eq!(truncate_line("abc", 3), "abc");
+        assert_eq!(truncate_line("abcd", 3), "ab…");
+        assert_eq!(truncate_line("abcd", 0), "");
+    }
+
+    #[test]
+    fn test_header() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _root = info_span!(target: "app", "root");
+        let snapshot = span_dumper.snapshot();
+        let options = DumpOptions {
+            header: true,
+            stable_ids: true,
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        snapshot
+            .dump_to_with(&mut out, DumpFormat::Tree, &options)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "# captured_at={} count=1\nroot (app) id=#1\n",
+                rfc3339(snapshot.captured_at().unwrap())
+            )
+        );
+
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + std::time::Duration::from_millis(1_709_210_096_789);
+        assert_eq!(rfc3339(time), "2024-02-29T12:34:56.789Z");
+    }
+
+    #[test]
+    fn test_empty_message() {
+        let snapshot = SpanSnapshot::default();
+        let dump = |format, options: &DumpOptions| {
+            let mut out = Vec::new();
+            snapshot.dump_to_with(&mut out, format, options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let options = DumpOptions::default();
+        assert_eq!(dump(DumpFormat::Text, &options), "no open spans\n");
+        #[cfg(feature = "serde")]
+        assert_eq!(dump(DumpFormat::Json, &options), "[]\n");
+
+        let options = DumpOptions {
+            empty_message: Some("idle".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(dump(DumpFormat::Tree, &options), "idle\n");
+
+        let options = DumpOptions {
+            header: true,
+            empty_message: None,
+            ..Default::default()
+        };
+        let verbose = dump(DumpFormat::Verbose, &options);
+        assert!(verbose.starts_with("# captured_at="));
+        assert!(verbose.ends_with(" count=0\n"));
+        assert_eq!(verbose.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_folded_stacks() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let request = info_span!(parent: &root, "request");
+        let _a = info_span!(parent: &request, "db;query");
+        let _b = info_span!(parent: &request, "db;query");
+        let _idle = info_span!(parent: &root, "idle");
+        let _other = info_span!("other");
+
+        assert_eq!(
+            span_dumper.snapshot().to_folded_stacks(),
+            "other 1\nroot;idle 1\nroot;request;db:query 2\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_context() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!(target: "app", "root");
+        let request = info_span!(target: "app", parent: &root, "request");
+        let query = info_span!(target: "app", parent: &request, "query");
+        let _nested = info_span!(target: "app", parent: &query, "nested");
+        let _other = info_span!(target: "app", parent: &root, "other");
+        let [root_id, request_id, query_id] = [&root, &request, &query].map(|s| s.id().unwrap());
+
+        let snapshot = span_dumper.snapshot();
+        let mut out = Vec::new();
+        snapshot.dump_context_to(&request_id, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "root (app) id={}\n  > request (app) id={}\n    query (app) id={}\n",
+                root_id.into_u64(),
+                request_id.into_u64(),
+                query_id.into_u64()
+            )
+        );
+
+        let mut out = Vec::new();
+        snapshot
+            .dump_context_to(&span::Id::from_u64(9999), &mut out)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "span id=9999 not found\n");
+    }
+
+    #[test]
+    fn test_dump_text_fields() {
+        let span_dumper = SpanDumpLayer::builder().capture_fields(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _request = info_span!(target: "app", "request", method = "GET", status = 200);
+        let _idle = info_span!(target: "app", "idle");
+
+        let text = |options: &DumpOptions| {
+            let mut out = Vec::new();
+            span_dumper
+                .snapshot()
+                .dump_to_with(&mut out, DumpFormat::Text, options)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let options = DumpOptions {
+            stable_ids: true,
+            ..Default::default()
+        };
+        let lines: Vec<_> = text(&options).lines().map(str::to_string).collect();
+        assert!(lines[0].starts_with("INFO request{method=GET, status=200} (app) id=#1 "));
+        assert!(lines[1].starts_with("INFO idle (app) id=#2 "));
+
+        let options = DumpOptions {
+            empty_fields: true,
+            ..options
+        };
+        assert!(text(&options).contains("INFO idle{} (app) id=#2 "));
+    }
+
+    #[test]
+    fn test_collapse_identical_siblings() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!(target: "app", "root");
+        let items: Vec<_> = (0..5)
+            .map(|_| info_span!(target: "app", parent: &root, "item"))
+            .collect();
+        let _step = info_span!(target: "app", parent: &items[0], "step");
+        let _other = info_span!(target: "app", parent: &root, "other");
+
+        let options = DumpOptions {
+            tree_style: TreeStyle::unicode(),
+            stable_ids: true,
+            collapse_identical_siblings: true,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        span_dumper
+            .snapshot()
+            .dump_to_with(&mut out, DumpFormat::Tree, &options)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "root (app) id=#1
+├── item (x5)
+│   ├── item (app) id=#2
+│   │   └── step (app) id=#7
+│   ├── item (app) id=#3
+│   ├── item (app) id=#4
+│   └── ... (2 more)
+└── other (app) id=#8
+"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::PoisonError;
+
+/// The error returned by the crate's fallible operations.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SpanDumpError {
+    /// Writing a dump or reading or writing a stream failed.
+    Io(io::Error),
+    /// Encoding or decoding JSON or a tail frame failed.
+    Serialize(Box<dyn Error + Send + Sync>),
+    /// A thread panicked while holding one of the layer's locks.
+    Poisoned,
+    /// Nothing was stored under the name, e.g. for
+    /// [`SpanDumpLayer::diff_from_checkpoint`](crate::SpanDumpLayer::diff_from_checkpoint).
+    NotFound(String),
+}
+
+impl fmt::Display for SpanDumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpanDumpError::Io(err) => write!(f, "I/O error: {}", err),
+            SpanDumpError::Serialize(err) => write!(f, "serialization error: {}", err),
+            SpanDumpError::Poisoned => f.write_str("a span dump lock was poisoned"),
+            SpanDumpError::NotFound(name) => write!(f, "nothing stored under {:?}", name),
+        }
+    }
+}
+
+impl Error for SpanDumpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SpanDumpError::Io(err) => Some(err),
+            SpanDumpError::Serialize(err) => Some(&**err),
+            SpanDumpError::Poisoned | SpanDumpError::NotFound(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for SpanDumpError {
+    fn from(err: io::Error) -> Self {
+        SpanDumpError::Io(err)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for SpanDumpError {
+    fn from(err: serde_json::Error) -> Self {
+        SpanDumpError::Serialize(Box::new(err))
+    }
+}
+
+#[cfg(feature = "tail")]
+impl From<bincode::Error> for SpanDumpError {
+    fn from(err: bincode::Error) -> Self {
+        SpanDumpError::Serialize(err)
+    }
+}
+
+impl<T> From<PoisonError<T>> for SpanDumpError {
+    fn from(_: PoisonError<T>) -> Self {
+        SpanDumpError::Poisoned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let err = SpanDumpError::from(io::Error::new(io::ErrorKind::BrokenPipe, "closed"));
+        assert_eq!(err.to_string(), "I/O error: closed");
+        assert!(err.source().is_some());
+
+        let lock = std::sync::Mutex::new(());
+        let _ = std::thread::scope(|s| {
+            s.spawn(|| {
+                let _guard = lock.lock().unwrap();
+                panic!("poison the lock");
+            })
+            .join()
+        });
+        let err = SpanDumpError::from(lock.lock().unwrap_err());
+        assert!(matches!(err, SpanDumpError::Poisoned));
+        assert!(err.source().is_none());
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+
+use tracing::span;
+
+use crate::stats::Stats;
+use crate::SpanRecord;
+
+/// A change to the set of open spans, see
+/// [`SpanDumpLayer::subscribe`](crate::SpanDumpLayer::subscribe).
+#[derive(Debug, Clone)]
+pub enum SpanEvent {
+    Opened(Box<SpanRecord>),
+    Closed(span::Id),
+}
+
+/// The senders of every live [`SpanDumpLayer::subscribe`](crate::SpanDumpLayer::subscribe)
+/// channel.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    senders: Mutex<Vec<SyncSender<SpanEvent>>>,
+    pub(crate) len: AtomicUsize,
+}
+
+impl Subscribers {
+    pub(crate) fn subscribe(&self, capacity: usize) -> Receiver<SpanEvent> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let mut senders = self.senders.lock().unwrap();
+        senders.push(sender);
+        self.len.store(senders.len(), Ordering::Relaxed);
+        receiver
+    }
+
+    /// Sends the event made by `event` to every subscriber without
+    /// blocking. Full channels miss the event and count it in `stats`;
+    /// disconnected ones are removed.
+    pub(crate) fn publish(&self, stats: &Stats, event: impl FnOnce() -> SpanEvent) {
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let event = event();
+        let mut senders = self.senders.lock().unwrap();
+        senders.retain(|sender| match sender.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                Stats::incr(&stats.dropped_events);
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+        self.len.store(senders.len(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_subscribe() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let receiver = span_dumper.subscribe();
+        let full = span_dumper.subscribe_with_capacity(1);
+
+        let span = info_span!("request");
+        let id = span.id().unwrap();
+        drop(span);
+
+        match receiver.try_recv().unwrap() {
+            SpanEvent::Opened(record) => assert_eq!(record.meta.name, "request"),
+            event => panic!("unexpected {:?}", event),
+        }
+        assert!(matches!(receiver.try_recv().unwrap(), SpanEvent::Closed(closed) if closed == id));
+        assert!(matches!(full.try_recv().unwrap(), SpanEvent::Opened(_)));
+        assert!(full.try_recv().is_err());
+        assert_eq!(span_dumper.stats().dropped_events, 1);
+
+        drop((receiver, full));
+        drop(info_span!("unobserved"));
+        assert_eq!(
+            span_dumper.shared.subscribers.len.load(Ordering::Relaxed),
+            0
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use tracing::Level;
+
+use crate::{DumpFormat, SpanDumpError, SpanRecord, SpanSnapshot};
+
+/// A render-time query over a snapshot's spans. Every condition that is set
+/// must match; an empty filter matches every span.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotFilter {
+    name: Option<String>,
+    target_prefix: Option<String>,
+    min_level: Option<Level>,
+    min_age: Option<Duration>,
+    fields: Vec<(String, String)>,
+    class: Option<u32>,
+}
+
+impl SnapshotFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only spans with exactly this name.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Only spans whose target starts with `prefix`.
+    pub fn target_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.target_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Only spans at least as severe as `level`, e.g. `WARN` keeps `WARN` and
+    /// `ERROR` spans.
+    pub fn min_level(mut self, level: Level) -> Self {
+        self.min_level = Some(level);
+        self
+    }
+
+    /// Only spans that have been open for at least `age`.
+    pub fn min_age(mut self, age: Duration) -> Self {
+        self.min_age = Some(age);
+        self
+    }
+
+    /// Only spans with a captured field `name` whose value renders as
+    /// `value`. May be given more than once.
+    pub fn field(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.fields.push((name.into(), value.into()));
+        self
+    }
+
+    /// Only spans tagged with `class` by
+    /// [`Builder::with_name_classifier`](crate::Builder::with_name_classifier).
+    pub fn class(mut self, class: u32) -> Self {
+        self.class = Some(class);
+        self
+    }
+
+    pub fn matches_name(&self, span: &SpanRecord) -> bool {
+        self.name.as_ref().is_none_or(|name| span.meta.name == name)
+    }
+
+    pub fn matches_target(&self, span: &SpanRecord) -> bool {
+        self.target_prefix
+            .as_ref()
+            .is_none_or(|prefix| span.meta.target.starts_with(prefix.as_str()))
+    }
+
+    pub fn matches_level(&self, span: &SpanRecord) -> bool {
+        self.min_level.is_none_or(|level| span.meta.level <= level)
+    }
+
+    pub fn matches_age(&self, span: &SpanRecord) -> bool {
+        self.matches_age_at(span, span.now())
+    }
+
+    /// Like [`SnapshotFilter::matches_age`], measuring the age at `now`.
+    pub fn matches_age_at(&self, span: &SpanRecord, now: Instant) -> bool {
+        self.min_age.is_none_or(|age| span.age_at(now) >= age)
+    }
+
+    pub fn matches_fields(&self, span: &SpanRecord) -> bool {
+        self.fields
+            .iter()
+            .all(|(name, value)| span.fields.get(name) == Some(value.as_str()))
+    }
+
+    pub fn matches_class(&self, span: &SpanRecord) -> bool {
+        self.class.is_none_or(|class| span.class == Some(class))
+    }
+
+    pub fn matches(&self, span: &SpanRecord) -> bool {
+        self.matches_at(span, span.now())
+    }
+
+    /// Like [`SnapshotFilter::matches`], measuring the span's age at `now`.
+    pub fn matches_at(&self, span: &SpanRecord, now: Instant) -> bool {
+        self.matches_name(span)
+            && self.matches_target(span)
+            && self.matches_level(span)
+            && self.matches_age_at(span, now)
+            && self.matches_fields(span)
+            && self.matches_class(span)
+    }
+}
+
+impl SpanSnapshot {
+    /// A copy of this snapshot holding only the spans matching `filter`.
+    pub fn filtered(&self, filter: &SnapshotFilter) -> SpanSnapshot {
+        let now = self.now();
+        let mut filtered = self.clone();
+        filtered.retain(|span| filter.matches_at(span, now));
+        filtered
+    }
+
+    /// Open spans, in creation order, that had been open for at least `age`
+    /// when the snapshot was taken.
+    pub fn spans_older_than(&self, age: Duration) -> Vec<&SpanRecord> {
+        self.spans_in_age_range(age, Duration::MAX)
+    }
+
+    /// Open spans, in creation order, whose age when the snapshot was taken
+    /// lies between `min` and `max`, both inclusive.
+    pub fn spans_in_age_range(&self, min: Duration, max: Duration) -> Vec<&SpanRecord> {
+        self.open_spans_by_creation()
+            .into_iter()
+            .filter(|span| (min..=max).contains(&self.age_of(span)))
+            .collect()
+    }
+
+    /// Writes only the spans matching `filter` in the given format. Parents
+    /// that are filtered out are not shown, so their children render as
+    /// roots in the tree formats.
+    pub fn dump_filtered_to<W: io::Write>(
+        &self,
+        w: &mut W,
+        filter: &SnapshotFilter,
+        format: DumpFormat,
+    ) -> Result<(), SpanDumpError> {
+        self.filtered(filter).dump_to(w, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{info_span, warn_span};
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_filter() {
+        let span_dumper = SpanDumpLayer::builder().capture_fields(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _query = info_span!(target: "db::pool", "query", table = "users");
+        let _other_query = warn_span!(target: "db::pool", "query", table = "orders");
+        let _request = warn_span!(target: "http", "request");
+
+        let snapshot = span_dumper.snapshot();
+        let names = |filter: SnapshotFilter| {
+            let mut names = snapshot
+                .filtered(&filter)
+                .open_spans_by_creation()
+                .iter()
+                .map(|span| {
+                    format!(
+                        "{}:{}",
+                        span.meta.name,
+                        span.fields.get("table").unwrap_or("-")
+                    )
+                })
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names(SnapshotFilter::new()).len(), 3);
+        assert_eq!(
+            names(SnapshotFilter::new().target_prefix("db")),
+            ["query:orders", "query:users"]
+        );
+        assert_eq!(
+            names(SnapshotFilter::new().min_level(Level::WARN)),
+            ["query:orders", "request:-"]
+        );
+        assert_eq!(
+            names(SnapshotFilter::new().name("query").field("table", "users")),
+            ["query:users"]
+        );
+        assert!(names(SnapshotFilter::new().min_age(Duration::from_secs(3600))).is_empty());
+
+        let mut out = Vec::new();
+        snapshot
+            .dump_filtered_to(
+                &mut out,
+                &SnapshotFilter::new().target_prefix("http"),
+                DumpFormat::Tree,
+            )
+            .unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("request (http)"));
+    }
+
+    #[test]
+    fn test_spans_in_age_range() {
+        let now = std::sync::Arc::new(std::sync::Mutex::new(Instant::now()));
+        let advance = |secs| *now.lock().unwrap() += Duration::from_secs(secs);
+        let span_dumper = SpanDumpLayer::builder()
+            .with_clock({
+                let now = now.clone();
+                move || *now.lock().unwrap()
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _stuck = info_span!("stuck");
+        advance(20);
+        let _slow = info_span!("slow");
+        advance(9);
+        let _edge = info_span!("edge");
+        advance(1);
+        let _new = info_span!("new");
+
+        let snapshot = span_dumper.snapshot();
+        let names =
+            |spans: Vec<&SpanRecord>| spans.iter().map(|span| span.meta.name).collect::<Vec<_>>();
+        let secs = Duration::from_secs;
+        assert_eq!(
+            names(snapshot.spans_in_age_range(secs(1), secs(10))),
+            ["slow", "edge"]
+        );
+        assert_eq!(
+            names(snapshot.spans_older_than(secs(10))),
+            ["stuck", "slow"]
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+use tracing_subscriber::util::{SubscriberInitExt, TryInitError};
+
+use crate::SpanDumpLayer;
+
+static GLOBAL: OnceLock<SpanDumpLayer> = OnceLock::new();
+
+/// Held while a layer is installed and stored, so [`init_global`] and
+/// [`set_global`] never store a layer other than the one installed.
+static INIT: Mutex<()> = Mutex::new(());
+
+/// The process-wide layer stored by [`set_global`] or [`init_global`].
+pub fn global() -> Option<&'static SpanDumpLayer> {
+    GLOBAL.get()
+}
+
+/// Stores `layer` as the process-wide layer without installing it, for
+/// applications that build their own subscriber stack. If a global layer is
+/// already stored, `layer` is discarded and the existing one is returned.
+pub fn set_global(layer: SpanDumpLayer) -> &'static SpanDumpLayer {
+    let _init = INIT.lock().unwrap_or_else(PoisonError::into_inner);
+    GLOBAL.get_or_init(|| layer)
+}
+
+/// Installs a new [`SpanDumpLayer`] on a registry as the global default
+/// subscriber and stores it as the process-wide layer.
+///
+/// Calling this again, or after [`set_global`], returns the stored layer.
+/// Fails if a different global subscriber has already been installed.
+///
+/// The stored layer is a clone of the installed one and so has no
+/// [`SpanDumpLayer::dispatch_id`], though it sees all of its spans.
+pub fn init_global() -> Result<&'static SpanDumpLayer, TryInitError> {
+    let _init = INIT.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(layer) = GLOBAL.get() {
+        return Ok(layer);
+    }
+
+    let layer = SpanDumpLayer::new();
+    tracing_subscriber::registry()
+        .with(layer.clone())
+        .try_init()?;
+    Ok(GLOBAL.get_or_init(|| layer))
+}
@@ -0,0 +1,22 @@
+use std::cell::Cell;
+
+thread_local! {
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the current thread as running layer-invoked user code, so events
+/// and spans it emits are not fed back into the layer's own triggers.
+pub(crate) struct ReentrancyGuard(());
+
+impl ReentrancyGuard {
+    /// `None` if the current thread already holds a guard.
+    pub(crate) fn enter() -> Option<Self> {
+        ACTIVE.with(|active| (!active.replace(true)).then_some(ReentrancyGuard(())))
+    }
+}
+
+impl Drop for ReentrancyGuard {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| active.set(false));
+    }
+}
@@ -0,0 +1,61 @@
+use std::io;
+
+use crate::{SpanDumpError, SpanDumpLayer, SpanSnapshot};
+
+/// The dump capabilities of a [`SpanDumpLayer`] as an object-safe trait, so
+/// code can hold an `Arc<dyn SpanDumpHandle>` and be tested with a mock
+/// instead of depending on the layer type.
+pub trait SpanDumpHandle: Send + Sync {
+    /// See [`SpanDumpLayer::snapshot`].
+    fn snapshot(&self) -> SpanSnapshot;
+
+    /// See [`SpanDumpLayer::count`].
+    fn open_span_count(&self) -> usize;
+
+    /// Writes a snapshot as [`SpanSnapshot::dump_text_to`] does.
+    fn dump_text_to(&self, mut w: &mut dyn io::Write) -> Result<(), SpanDumpError> {
+        self.snapshot().dump_text_to(&mut w)
+    }
+}
+
+impl SpanDumpHandle for SpanDumpLayer {
+    fn snapshot(&self) -> SpanSnapshot {
+        SpanDumpLayer::snapshot(self)
+    }
+
+    fn open_span_count(&self) -> usize {
+        self.count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+
+    #[test]
+    fn test_trait_object() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _s = info_span!(target: "app", "request");
+
+        let handle: Arc<dyn SpanDumpHandle> = Arc::new(span_dumper);
+        assert_eq!(handle.open_span_count(), 1);
+        assert_eq!(handle.snapshot().len(), 1);
+
+        let mut out = Vec::new();
+        handle.dump_text_to(&mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("INFO request (app) id="));
+    }
+}
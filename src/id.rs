@@ -0,0 +1,108 @@
+use tracing::span;
+
+/// A span id qualified by the process it came from, so ids from snapshots of
+/// several processes can be combined without clashing.
+///
+/// Process `0` stands for the local process: converting a [`span::Id`]
+/// gives a `NamespacedId` in it, and only those resolve in a snapshot taken
+/// from a layer, see [`SpanKey`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NamespacedId {
+    pub process: u32,
+    pub raw: u64,
+}
+
+impl NamespacedId {
+    /// The namespace of ids converted from a [`span::Id`].
+    pub const LOCAL_PROCESS: u32 = 0;
+
+    pub fn new(process: u32, id: &span::Id) -> Self {
+        NamespacedId {
+            process,
+            raw: id.into_u64(),
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.process == Self::LOCAL_PROCESS
+    }
+}
+
+impl From<&span::Id> for NamespacedId {
+    fn from(id: &span::Id) -> Self {
+        NamespacedId::new(NamespacedId::LOCAL_PROCESS, id)
+    }
+}
+
+impl From<span::Id> for NamespacedId {
+    fn from(id: span::Id) -> Self {
+        NamespacedId::from(&id)
+    }
+}
+
+/// Drops the process, for ids known to be local.
+///
+/// # Panics
+///
+/// Panics if `raw` is zero, as [`span::Id::from_u64`] does.
+impl From<NamespacedId> for span::Id {
+    fn from(id: NamespacedId) -> Self {
+        span::Id::from_u64(id.raw)
+    }
+}
+
+/// An id that snapshot lookups such as [`SpanSnapshot::get`](crate::SpanSnapshot::get)
+/// and [`SpanSnapshot::ancestors`](crate::SpanSnapshot::ancestors) accept:
+/// either a [`span::Id`] or a [`NamespacedId`].
+pub trait SpanKey {
+    /// The id within the local process, `None` if it belongs to another
+    /// process and so cannot be open in a local snapshot.
+    fn local_id(&self) -> Option<span::Id>;
+}
+
+impl SpanKey for span::Id {
+    fn local_id(&self) -> Option<span::Id> {
+        Some(self.clone())
+    }
+}
+
+impl SpanKey for NamespacedId {
+    fn local_id(&self) -> Option<span::Id> {
+        (self.is_local() && self.raw != 0).then(|| span::Id::from_u64(self.raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_namespaced_lookup() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let child = info_span!(parent: &root, "child");
+        let (root_id, child_id) = (root.id().unwrap(), child.id().unwrap());
+
+        let local = NamespacedId::from(&child_id);
+        assert_eq!(span::Id::from(local), child_id);
+
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(
+            snapshot.get(&local).map(|span| span.meta.name),
+            Some("child")
+        );
+        assert_eq!(snapshot.ancestors(&local)[0].id, root_id);
+        assert!(snapshot.get(&NamespacedId::new(1, &child_id)).is_none());
+        assert!(snapshot.get(&NamespacedId { process: 0, raw: 0 }).is_none());
+    }
+}
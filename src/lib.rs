@@ -1,71 +1,833 @@
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+//! A [`tracing_subscriber::Layer`] that keeps a record of every open span so
+//! the current state of a program can be dumped for debugging.
+//!
+//! The crate requires `std`. Records hold `Instant` and `SystemTime`
+//! timestamps and the layer builds on `tracing-subscriber`'s `Layer` and
+//! `Registry`, none of which are available under `no_std`. The data model
+//! ([`SpanRecord`], [`SpanSnapshot`]) is kept separate from the formatters
+//! that render it to text or IO.
+//!
+//! The layer never filters for the rest of the subscriber. In a layered
+//! subscriber a layer that reports a span or event as disabled disables it
+//! for every layer, so this one always reports them as enabled and applies
+//! its own filters, such as [`Builder::capture_if`], only to what it
+//! records. Conversely it only sees spans that the subscriber as a whole
+//! enables: put other layers' filters on those layers with
+//! [`Layer::with_filter`] rather than on the whole subscriber to keep the
+//! spans they reject in dumps.
 
-use tracing::{span, Subscriber};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{
+    Arc, Mutex, OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+};
+use std::time::{Duration, SystemTime};
+
+use tracing::dispatcher::WeakDispatch;
+use tracing::subscriber::Interest;
+use tracing::{span, Dispatch, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::Layer;
 
-#[derive(Debug, Clone)]
-pub struct SpanRecord {
-    pub id: span::Id,
-    pub parent: Option<span::Id>,
-    pub metadata: &'static tracing::Metadata<'static>,
+mod builder;
+mod diff;
+#[cfg(feature = "serde")]
+mod dto;
+mod dump;
+mod error;
+mod events;
+mod filter;
+mod global;
+mod guard;
+mod handle;
+mod id;
+mod lifecycle;
+mod limits;
+#[cfg(feature = "perfetto")]
+mod perfetto;
+mod record;
+mod report;
+mod retain;
+mod snapshot;
+mod stats;
+#[cfg(feature = "tail")]
+pub mod tail;
+#[cfg(any(test, feature = "test-util"))]
+mod test_util;
+mod validate;
+mod view;
+
+pub use builder::Builder;
+pub use diff::SnapshotDiff;
+#[cfg(feature = "serde")]
+pub use dto::{SpanEventDto, SpanRecordDto, SpanTreeDto};
+pub use dump::{DumpFormat, DumpOnDrop, DumpOptions, TreeStyle, NO_OPEN_SPANS};
+pub use error::SpanDumpError;
+pub use events::SpanEvent;
+pub use filter::SnapshotFilter;
+pub use global::{global, init_global, set_global};
+pub use handle::SpanDumpHandle;
+pub use id::{NamespacedId, SpanKey};
+pub use lifecycle::LIFECYCLE_TARGET;
+pub use record::{
+    FieldValue, FilterDecision, ParentKind, ParentStrategy, SpanExtensions, SpanFields,
+    SpanLifetime, SpanMeta, SpanRecord, SpanTiming, REDACTED, TRUNCATED,
+};
+pub use snapshot::SpanSnapshot;
+pub use stats::LayerStats;
+#[cfg(feature = "test-util")]
+pub use test_util::{assert_tree_matches, PanicDumpGuard};
+pub use validate::SnapshotWarning;
+pub use view::SpanView;
+
+use builder::Config;
+use events::Subscribers;
+use limits::NameLimits;
+use stats::Stats;
+
+pub(crate) struct Shared {
+    pub(crate) config: Config,
+    pub(crate) spans: RwLock<SpanSnapshot>,
+    pub(crate) postmortem: Mutex<VecDeque<SpanLifetime>>,
+    pub(crate) threshold_armed: AtomicBool,
+    pub(crate) high_water: AtomicUsize,
+    pub(crate) stats: Stats,
+    pub(crate) checkpoints: Mutex<VecDeque<(String, SpanSnapshot)>>,
+    pub(crate) recent: Mutex<HashMap<&'static str, VecDeque<SpanLifetime>>>,
+    pub(crate) retained: Mutex<retain::RetainedChildren>,
+    pub(crate) subscribers: Subscribers,
+    pub(crate) name_limits: NameLimits,
+    #[cfg(feature = "hdrhistogram")]
+    pub(crate) histograms: Mutex<HashMap<&'static str, hdrhistogram::Histogram<u64>>>,
+}
+
+impl Shared {
+    /// Takes the write lock, counting the times it had to wait in
+    /// [`LayerStats::write_lock_contended`].
+    ///
+    /// Like [`Shared::read_spans`] this ignores poisoning: the map is never
+    /// left half-updated, so a panic elsewhere must not stop tracing.
+    pub(crate) fn write_spans(&self) -> RwLockWriteGuard<'_, SpanSnapshot> {
+        match self.spans.try_write() {
+            Ok(spans) => return spans,
+            Err(TryLockError::WouldBlock) => Stats::incr(&self.stats.write_contended),
+            Err(TryLockError::Poisoned(spans)) => return spans.into_inner(),
+        }
+        self.spans.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Takes the read lock, recovering the map if a thread panicked while
+    /// holding the write lock.
+    pub(crate) fn read_spans(&self) -> RwLockReadGuard<'_, SpanSnapshot> {
+        self.spans.read().unwrap_or_else(PoisonError::into_inner)
+    }
 }
 
-#[derive(Clone, Default)]
-pub struct SpanSnapshot {
-    spans: HashMap<span::Id, SpanRecord>,
+pub struct SpanDumpLayer {
+    pub(crate) shared: Arc<Shared>,
+    pub(crate) dispatch_id: OnceLock<u64>,
+    pub(crate) dispatch: OnceLock<WeakDispatch>,
 }
 
-impl SpanSnapshot {
-    pub fn open_spans(&self) -> impl Iterator<Item = &SpanRecord> {
-        self.spans.values()
+/// A record held in the registry's span extensions until the span is first
+/// entered, see [`Builder::capture_on_enter`].
+struct PendingRecord(SpanRecord);
+
+/// Marks a span in the registry's span extensions once it has been entered,
+/// so [`SpanRecord::was_entered`] is only written on the first enter when
+/// enters are not otherwise tracked.
+struct Entered;
+
+/// How many checkpoints [`SpanDumpLayer::checkpoint`] keeps.
+pub const MAX_CHECKPOINTS: usize = 16;
+
+/// The channel capacity used by [`SpanDumpLayer::subscribe`].
+pub const DEFAULT_SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// Latencies above one hour are recorded as one hour.
+#[cfg(feature = "hdrhistogram")]
+const MAX_LATENCY_MICROS: u64 = 3_600_000_000;
+
+/// Source of [`SpanDumpLayer::dispatch_id`]s, shared by all layers.
+static NEXT_DISPATCH_ID: AtomicU64 = AtomicU64::new(1);
+
+impl SpanDumpLayer {
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// A copy of the open spans.
+    ///
+    /// The span map sits behind a single lock that span creation and close
+    /// take for writing, so a snapshot is one consistent view and reflects
+    /// every span whose creation returned before it was taken: a thread that
+    /// opens a span and then snapshots always sees it, as does any thread
+    /// that learned of the span afterwards, e.g. through a channel. The
+    /// exceptions are spans held back by [`Builder::capture_if`],
+    /// [`Builder::sample_rate`] or, until first entered,
+    /// [`Builder::capture_on_enter`]. The chunked
+    /// [`SpanDumpLayer::snapshot_async`] and
+    /// [`SpanDumpLayer::stream_ndjson`] give up the single view, as
+    /// documented there.
+    pub fn snapshot(&self) -> SpanSnapshot {
+        let mut snapshot = self.shared.read_spans().clone();
+        snapshot.set_taken_at(self.shared.config.now());
+        snapshot
+    }
+
+    /// Like [`SpanDumpLayer::snapshot`], but also including the closed spans
+    /// kept by [`Builder::retain_closed_children`], so a request's whole tree
+    /// can be dumped while its root is still open. Closed spans have
+    /// [`SpanRecord::closed_at`] set and, despite the name, are listed by
+    /// [`SpanSnapshot::open_spans`] along with the open ones.
+    pub fn snapshot_with_closed(&self) -> SpanSnapshot {
+        let spans = self.shared.read_spans();
+        let mut snapshot = spans.clone();
+        for lifetime in self.shared.retained.lock().unwrap().iter() {
+            snapshot.insert_closed(SpanRecord {
+                closed_at: Some(lifetime.closed_at),
+                ..lifetime.record.clone()
+            });
+        }
+        drop(spans);
+        snapshot.set_taken_at(self.shared.config.now());
+        snapshot
+    }
+
+    /// A snapshot of only the spans currently entered on some thread, leaving
+    /// out spans that are open but idle, such as parked futures.
+    ///
+    /// Unlike [`SpanDumpLayer::snapshot`] this copies only the entered
+    /// records, and it requires [`Builder::track_enters`]: without it no span
+    /// is known to be entered and the snapshot is always empty. The parents
+    /// of entered spans are not included unless entered themselves, so tree
+    /// dumps show entered spans under idle parents as roots.
+    pub fn active_snapshot(&self) -> SpanSnapshot {
+        let mut snapshot = self
+            .shared
+            .read_spans()
+            .copy_matching(|span| span.timing.is_entered());
+        snapshot.set_taken_at(self.shared.config.now());
+        snapshot
+    }
+
+    /// Like [`SpanDumpLayer::snapshot`], but refills `buf` in place so a
+    /// periodic dump can reuse one snapshot's map allocation rather than
+    /// building a new one each time.
+    pub fn snapshot_into(&self, buf: &mut SpanSnapshot) {
+        buf.refill_from(&self.shared.read_spans());
+        buf.set_taken_at(self.shared.config.now());
+    }
+
+    /// Takes a snapshot and stops tracking every span in it, in one step so
+    /// no span opened or closed in between is lost or counted twice, e.g. to
+    /// start each window of an aggregation afresh.
+    ///
+    /// Drained spans are forgotten even though they are still open: closing
+    /// them later does nothing, and they no longer count towards
+    /// [`Builder::max_spans_for_name`] or [`Builder::on_threshold_exceeded`].
+    /// Closed spans kept for them by [`Builder::retain_closed_children`] are
+    /// dropped.
+    pub fn drain(&self) -> SpanSnapshot {
+        let mut snapshot = {
+            let mut spans = self.shared.write_spans();
+            *self.shared.retained.lock().unwrap() = Default::default();
+            spans.take()
+        };
+        snapshot.set_taken_at(self.shared.config.now());
+
+        for span in snapshot.open_spans() {
+            self.shared.name_limits.closed(span.meta.name);
+        }
+        self.check_threshold(0);
+        snapshot
+    }
+
+    /// Calls `f` with the layer's live span map instead of copying it.
+    ///
+    /// The layer's read lock is held while `f` runs, so `f` must not create
+    /// or close spans on the same layer, and should return quickly.
+    pub fn with_snapshot<R>(&self, f: impl FnOnce(&SpanSnapshot) -> R) -> R {
+        f(&self.shared.read_spans())
+    }
+
+    /// Copies out [`SpanSnapshot::hot_path`], the deepest chain of entered
+    /// spans, without copying the rest of the span map.
+    pub fn hot_path(&self) -> Vec<SpanRecord> {
+        self.with_snapshot(|snapshot| snapshot.hot_path().into_iter().cloned().collect())
+    }
+
+    /// The [`SpanSnapshot::path_of`] of the current span, for quick debug
+    /// prints, or `"<none>"` outside any span this layer tracks.
+    ///
+    /// The current span is resolved through [`tracing::Span::current`], so the
+    /// calling thread's default subscriber must be the one this layer is in.
+    pub fn where_am_i(&self) -> String {
+        tracing::Span::current()
+            .id()
+            .and_then(|id| self.with_snapshot(|snapshot| snapshot.path_of(&id)))
+            .unwrap_or_else(|| "<none>".to_string())
+    }
+
+    /// Calls `f` with the live record of the open span `id`, e.g. to attach
+    /// [`SpanExtensions`] from another layer, and returns its result. `None`
+    /// if the span is not tracked (yet, with
+    /// [`Builder::capture_on_enter`]).
+    ///
+    /// The layer's write lock is held while `f` runs, so as with
+    /// [`SpanDumpLayer::with_snapshot`] it must not create or close spans on
+    /// the same layer. If `f` panics the layer keeps working, but the record
+    /// keeps whatever changes `f` made before panicking.
+    pub fn with_record_mut<R>(
+        &self,
+        id: &span::Id,
+        f: impl FnOnce(&mut SpanRecord) -> R,
+    ) -> Option<R> {
+        self.shared.write_spans().get_mut(id).map(f)
+    }
+
+    /// Like [`SpanDumpLayer::snapshot`], but copies the spans in bounded
+    /// chunks and yields to the tokio runtime between them, so a very large
+    /// snapshot does not hold the lock (and a worker) for the whole copy.
+    ///
+    /// This trades latency for fairness. Spans may open or close between
+    /// chunks: spans opened after the call are not included, and spans that
+    /// close part way through may be missing, so the result is not a single
+    /// consistent view.
+    #[cfg(feature = "tokio")]
+    pub async fn snapshot_async(&self) -> SpanSnapshot {
+        const CHUNK_SIZE: usize = 256;
+
+        let (mut snapshot, ids) = {
+            let spans_read = self.shared.read_spans();
+            (SpanSnapshot::empty_like(&spans_read), spans_read.ids())
+        };
+        snapshot.set_taken_at(self.shared.config.now());
+
+        for chunk in ids.chunks(CHUNK_SIZE) {
+            {
+                let spans_read = self.shared.read_spans();
+                snapshot.copy_from(&spans_read, chunk);
+            }
+            tokio::task::yield_now().await;
+        }
+
+        snapshot
+    }
+
+    /// Stores a snapshot under `name` for [`SpanDumpLayer::diff_from_checkpoint`],
+    /// replacing any earlier checkpoint with the same name.
+    ///
+    /// At most [`MAX_CHECKPOINTS`] are kept; storing another evicts the
+    /// checkpoint that was stored longest ago.
+    pub fn checkpoint(&self, name: &str) {
+        let snapshot = self.snapshot();
+        let mut checkpoints = self.shared.checkpoints.lock().unwrap();
+        checkpoints.retain(|(existing, _)| existing != name);
+        if checkpoints.len() == MAX_CHECKPOINTS {
+            checkpoints.pop_front();
+        }
+        checkpoints.push_back((name.to_string(), snapshot));
+    }
+
+    /// The spans opened and closed since the checkpoint `name` was stored,
+    /// or [`SpanDumpError::NotFound`] if there is no such checkpoint.
+    pub fn diff_from_checkpoint(&self, name: &str) -> Result<SnapshotDiff, SpanDumpError> {
+        let now = self.snapshot();
+        let checkpoints = self.shared.checkpoints.lock()?;
+        let (_, checkpoint) = checkpoints
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .ok_or_else(|| SpanDumpError::NotFound(name.to_string()))?;
+        Ok(checkpoint.diff(&now))
+    }
+
+    /// Returns a channel receiving an event for every span the layer starts
+    /// or stops tracking from now on, buffering up to
+    /// [`DEFAULT_SUBSCRIBER_CAPACITY`] events.
+    ///
+    /// The layer never blocks on a slow receiver: events that do not fit in
+    /// the channel are dropped and counted in
+    /// [`LayerStats::dropped_events`]. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> std::sync::mpsc::Receiver<SpanEvent> {
+        self.subscribe_with_capacity(DEFAULT_SUBSCRIBER_CAPACITY)
+    }
+
+    /// Like [`SpanDumpLayer::subscribe`] with a channel holding up to
+    /// `capacity` events.
+    pub fn subscribe_with_capacity(&self, capacity: usize) -> std::sync::mpsc::Receiver<SpanEvent> {
+        self.shared.subscribers.subscribe(capacity)
+    }
+
+    /// The id assigned to this layer value when it was installed in a
+    /// dispatcher, or `None` if it has not been installed.
+    ///
+    /// `tracing` does not give dispatchers a stable identity, so the layer
+    /// numbers itself instead: each clone installed into a subscriber takes
+    /// the next id, and records it on every span it creates (see
+    /// [`SpanRecord::dispatch_id`]). Clones share their spans but not their
+    /// id, so installing clones of one layer into several per-thread
+    /// subscribers tags each span with the subscriber it came through.
+    pub fn dispatch_id(&self) -> Option<u64> {
+        self.dispatch_id.get().copied()
     }
 
-    pub fn dump_text(&self) {
-        // let spans = open_spans()
+    pub fn stats(&self) -> LayerStats {
+        self.shared.stats.load()
+    }
+
+    /// Whether the layer's lock is currently held, by a reader or a writer.
+    ///
+    /// This is a diagnostic for the layer's own overhead, together with
+    /// [`LayerStats::write_lock_contended`]: the answer may be stale by the
+    /// time it is returned.
+    pub fn is_locked(&self) -> bool {
+        matches!(self.shared.spans.try_write(), Err(TryLockError::WouldBlock))
+    }
+
+    /// The number of currently open spans.
+    pub fn count(&self) -> usize {
+        self.shared.read_spans().len()
+    }
+
+    /// The most spans that were open at once since the layer was built or
+    /// [`SpanDumpLayer::reset_high_water`] was last called.
+    pub fn max_open_spans(&self) -> usize {
+        self.shared.high_water.load(Ordering::Relaxed)
+    }
+
+    /// Restarts [`SpanDumpLayer::max_open_spans`] from the current count,
+    /// e.g. to measure a new phase of the program.
+    pub fn reset_high_water(&self) {
+        self.shared
+            .high_water
+            .store(self.count(), Ordering::Relaxed);
+    }
+
+    /// The most recently closed spans, oldest first. Empty unless enabled with
+    /// [`Builder::postmortem`].
+    pub fn postmortem(&self) -> Vec<SpanLifetime> {
+        let postmortem = self.shared.postmortem.lock().unwrap();
+        postmortem.iter().cloned().collect()
+    }
+
+    /// The most recently closed spans named `name`, oldest first. Empty
+    /// unless enabled with [`Builder::retain_recent_per_name`].
+    pub fn recent_for_name(&self, name: &str) -> Vec<SpanLifetime> {
+        let recent = self.shared.recent.lock().unwrap();
+        recent
+            .get(name)
+            .map(|spans| spans.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns a guard that writes the open spans to stderr when it is
+    /// dropped. The snapshot is taken at drop time, so it reflects the state
+    /// on scope exit.
+    pub fn dump_on_drop(&self, format: DumpFormat) -> DumpOnDrop {
+        DumpOnDrop::new(self.clone(), format, io::stderr())
+    }
+
+    /// Like [`SpanDumpLayer::dump_on_drop`], writing to `writer` instead.
+    pub fn dump_on_drop_to<W: io::Write>(&self, format: DumpFormat, writer: W) -> DumpOnDrop<W> {
+        DumpOnDrop::new(self.clone(), format, writer)
+    }
+
+    fn new_record<S>(
+        &self,
+        attrs: &span::Attributes<'_>,
+        id: &span::Id,
+        ctx: &tracing_subscriber::layer::Context<'_, S>,
+    ) -> SpanRecord
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let parent_kind = if let Some(parent) = attrs.parent() {
+            ParentKind::Explicit(parent.clone())
+        } else if attrs.is_contextual() {
+            ctx.current_span()
+                .id()
+                .cloned()
+                .map_or(ParentKind::Root, ParentKind::Contextual)
+        } else {
+            ParentKind::Root
+        };
+
+        let mut fields = SpanFields::default();
+        if self.shared.config.capture_fields {
+            attrs.record(&mut fields.capture(&self.shared.config.field_rules));
+        }
 
-        // let mut spans_read = self.spans.read().unwrap();
+        let ancestry = if self.shared.config.capture_ancestry {
+            ctx.span_scope(id)
+                .map(|scope| scope.skip(1).map(|span| span.id()).collect())
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
 
-        // for (span_id, span) in spans_read.iter() {
-        //     println!("{}", span.metadata.name());
-        // }
+        let mut record = self.base_record(id, attrs.metadata(), parent_kind, fields, ancestry);
+        if self.shared.config.parent_strategy == ParentStrategy::Both {
+            record.contextual_parent = ctx.current_span().id().cloned();
+        }
+        #[cfg(feature = "backtrace")]
+        if let Some(rate) = self.shared.config.backtrace_rate {
+            if stats::keep_sample(&self.shared.stats.backtrace_seen, rate) {
+                record.backtrace = Some(Arc::new(std::backtrace::Backtrace::force_capture()));
+            }
+        }
+        record
     }
 
-    fn new_span(&mut self, attrs: &span::Attributes<'_>, id: &span::Id) {
-        self.spans.insert(
-            id.clone(),
-            SpanRecord {
-                id: id.clone(),
-                parent: attrs.parent().cloned(),
-                metadata: attrs.metadata(),
+    /// A record for span `id` opened now.
+    fn base_record(
+        &self,
+        id: &span::Id,
+        metadata: &'static tracing::Metadata<'static>,
+        parent_kind: ParentKind,
+        fields: SpanFields,
+        ancestry: Vec<span::Id>,
+    ) -> SpanRecord {
+        SpanRecord {
+            id: id.clone(),
+            parent: self.shared.config.parent_strategy.parent(&parent_kind),
+            parent_kind,
+            contextual_parent: None,
+            metadata,
+            meta: SpanMeta::from(metadata),
+            seq: 0,
+            opened_at: self.shared.config.now(),
+            closed_at: None,
+            was_entered: false,
+            events: 0,
+            children_opened: 0,
+            clock: record::RecordClock(self.shared.config.clock.clone()),
+            filter: if self.shared.config.capture_if.is_some()
+                || !self.shared.config.required_fields.is_empty()
+            {
+                FilterDecision::Passed
+            } else {
+                FilterDecision::Unfiltered
             },
-        );
+            created_at: SystemTime::now(),
+            fields,
+            ancestry,
+            dispatch_id: self.dispatch_id(),
+            timing: SpanTiming::default(),
+            #[cfg(feature = "tokio-task-id")]
+            task_id: tokio::task::try_id(),
+            #[cfg(feature = "backtrace")]
+            backtrace: None,
+            sibling_index: None,
+            class: self
+                .shared
+                .config
+                .name_classifier
+                .as_ref()
+                .and_then(|classify| classify(metadata.name())),
+            extensions: record::SpanExtensions::default(),
+        }
+    }
+
+    /// Tracks the spans that were already entered on this thread when the
+    /// layer was installed, for layers added to a running subscriber after
+    /// spans were opened. `S` is the registry type behind `dispatch`,
+    /// usually [`tracing_subscriber::Registry`]. Returns how many spans were
+    /// added.
+    ///
+    /// The registry cannot be enumerated, so only the current span and its
+    /// ancestors are found: spans open elsewhere, or idle on this thread,
+    /// are still missing. The span's fields were recorded before the layer
+    /// saw them and are left empty, its parent is reported as contextual,
+    /// and its age counts from the backfill. Spans already tracked, or
+    /// rejected by [`Builder::capture_if`], are skipped. Does nothing if
+    /// `dispatch` is not backed by an `S`.
+    pub fn backfill_from<S>(&self, dispatch: &Dispatch) -> usize
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let Some(registry) = dispatch.downcast_ref::<S>() else {
+            return 0;
+        };
+        let Some(current) = dispatch
+            .current_span()
+            .id()
+            .and_then(|id| registry.span(id))
+        else {
+            return 0;
+        };
+
+        let mut added = 0;
+        for span in current.scope().from_root() {
+            let id = span.id();
+            let wanted = self
+                .shared
+                .config
+                .capture_if
+                .as_ref()
+                .is_none_or(|capture_if| capture_if(span.metadata()));
+            if !wanted || self.shared.read_spans().get(&id).is_some() {
+                continue;
+            }
+
+            let parent_kind = span.parent().map_or(ParentKind::Root, |parent| {
+                ParentKind::Contextual(parent.id())
+            });
+            let ancestry = if self.shared.config.capture_ancestry {
+                span.scope().skip(1).map(|span| span.id()).collect()
+            } else {
+                Vec::new()
+            };
+            // Spans in the current scope are entered.
+            let mut record = self.base_record(
+                &id,
+                span.metadata(),
+                parent_kind,
+                SpanFields::default(),
+                ancestry,
+            );
+            record.was_entered = true;
+            self.track(record);
+            added += 1;
+        }
+        added
+    }
+
+    /// Inserts a newly tracked span into the map.
+    fn track(&self, mut record: SpanRecord) {
+        if let Some(transform) = &self.shared.config.record_transform {
+            transform(&mut record);
+        }
+        let (id, name) = (record.id.clone(), record.meta.name);
+        let (inserted, over_limit, count) = {
+            let mut spans_write = self.shared.write_spans();
+            let inserted = spans_write.insert(record);
+            if inserted && self.shared.config.sibling_index {
+                spans_write.number_sibling(&id);
+            }
+            // Counted under the lock so a concurrent `drain` never uncounts
+            // a span before it was counted.
+            let over_limit = inserted
+                .then(|| self.shared.name_limits.opened(name))
+                .flatten();
+            // Published under the lock so the event holds the record as
+            // inserted, without cloning it when nobody subscribed.
+            if inserted {
+                self.shared.subscribers.publish(&self.shared.stats, || {
+                    let record = spans_write.get(&id).expect("just inserted");
+                    SpanEvent::Opened(Box::new(record.clone()))
+                });
+            }
+            (inserted, over_limit, spans_write.len())
+        };
+
+        self.shared.high_water.fetch_max(count, Ordering::Relaxed);
+        self.check_threshold(count);
+        // A duplicate delivery of `on_new_span` merges into the tracked
+        // record and is not a new span.
+        if !inserted {
+            return;
+        }
+        Stats::incr(&self.shared.stats.tracked);
+        self.log_lifecycle("span opened", &id, name);
+        if let Some(open) = over_limit {
+            self.name_limit_exceeded(&id, name, open);
+        }
+    }
+
+    fn name_limit_exceeded(&self, id: &span::Id, name: &'static str, open: usize) {
+        if let Some(callback) = &self.shared.config.on_name_limit_exceeded {
+            callback(name, open);
+        } else if let Some(dispatch) = self.dispatch.get().and_then(WeakDispatch::upgrade) {
+            lifecycle::emit(
+                &dispatch,
+                tracing::Level::WARN,
+                "open spans exceeded the limit for their name",
+                id.into_u64(),
+                name,
+            );
+        }
+    }
+
+    fn check_threshold(&self, count: usize) {
+        let Some(threshold) = &self.shared.config.threshold else {
+            return;
+        };
+
+        if count > threshold.limit {
+            if self.shared.threshold_armed.swap(false, Ordering::AcqRel) {
+                (threshold.callback)(count);
+            }
+        } else if count < threshold.limit {
+            self.shared.threshold_armed.store(true, Ordering::Release);
+        }
+    }
+
+    fn log_lifecycle(&self, message: &str, id: &span::Id, name: &'static str) {
+        let Some(level) = self.shared.config.log_lifecycle else {
+            return;
+        };
+        if let Some(dispatch) = self.dispatch.get().and_then(WeakDispatch::upgrade) {
+            lifecycle::emit(&dispatch, level, message, id.into_u64(), name);
+        }
+    }
+
+    fn retain_recent(&self, lifetime: &SpanLifetime) {
+        let capacity = self.shared.config.recent_per_name;
+        if capacity == 0 {
+            return;
+        }
+
+        let mut recent = self.shared.recent.lock().unwrap();
+        let spans = recent.entry(lifetime.record.meta.name).or_default();
+        if spans.len() == capacity {
+            spans.pop_front();
+        }
+        spans.push_back(lifetime.clone());
+    }
+
+    /// Keeps a closed span for [`SpanDumpLayer::snapshot_with_closed`] while
+    /// its parent is open, or releases the spans kept under it. Called with
+    /// the span map's write lock held, so a parent cannot close in between.
+    fn retain_child(&self, spans: &SpanSnapshot, lifetime: &SpanLifetime) {
+        let max = self.shared.config.retain_closed_children;
+        if max == 0 {
+            return;
+        }
+
+        let mut retained = self.shared.retained.lock().unwrap();
+        let parent_open = lifetime
+            .record
+            .parent
+            .as_ref()
+            .is_some_and(|parent| spans.get(parent).is_some());
+        if !parent_open || !retained.retain(lifetime.clone(), max) {
+            retained.release(&lifetime.record.id);
+        }
+    }
+
+    fn retain_closed(&self, lifetime: SpanLifetime) {
+        let capacity = self.shared.config.postmortem_capacity;
+        if capacity == 0 {
+            return;
+        }
+
+        let mut postmortem = self.shared.postmortem.lock().unwrap();
+        if postmortem.len() == capacity {
+            postmortem.pop_front();
+        }
+        postmortem.push_back(lifetime);
+    }
+
+    /// The `q` quantile, between 0 and 1 (0.99 for p99), of the durations of
+    /// closed spans named `name`, read from their
+    /// [`SpanDumpLayer::latency_histogram`] and so accurate to its three
+    /// significant figures. `None` without data, for `q` out of range, and
+    /// always without the `hdrhistogram` feature.
+    pub fn duration_percentile(&self, name: &str, q: f64) -> Option<Duration> {
+        #[cfg(feature = "hdrhistogram")]
+        {
+            if !(0.0..=1.0).contains(&q) {
+                return None;
+            }
+            let histograms = self.shared.histograms.lock().unwrap();
+            let micros = histograms.get(name)?.value_at_quantile(q);
+            Some(Duration::from_micros(micros))
+        }
+        #[cfg(not(feature = "hdrhistogram"))]
+        {
+            let _ = (name, q);
+            None
+        }
+    }
+
+    /// The distribution of durations, in microseconds, of closed spans named
+    /// `name`. `None` until a span with that name has closed, and always
+    /// unless enabled with [`Builder::latency_histograms`].
+    #[cfg(feature = "hdrhistogram")]
+    pub fn latency_histogram(&self, name: &str) -> Option<hdrhistogram::Histogram<u64>> {
+        let histograms = self.shared.histograms.lock().unwrap();
+        histograms.get(name).cloned()
     }
 
-    fn close_span(&mut self, id: span::Id) {
-        self.spans.remove(&id);
+    #[cfg(feature = "hdrhistogram")]
+    fn record_latency(&self, lifetime: &SpanLifetime) {
+        if !self.shared.config.latency_histograms {
+            return;
+        }
+
+        let micros = u64::try_from(lifetime.duration().as_micros()).unwrap_or(u64::MAX);
+        let mut histograms = self.shared.histograms.lock().unwrap();
+        histograms
+            .entry(lifetime.record.meta.name)
+            .or_insert_with(|| {
+                // Fixed bounds rather than auto-resizing, which loses the
+                // minimum when a value first exceeds the initial range.
+                hdrhistogram::Histogram::new_with_max(MAX_LATENCY_MICROS, 3)
+                    .expect("bounds and 3 significant figures are valid")
+            })
+            .saturating_record(micros);
     }
 }
 
-#[derive(Clone)]
-pub struct SpanDumpLayer {
-    spans: Arc<RwLock<SpanSnapshot>>,
+/// Shows the open span count instead of the spans. The count is only read
+/// if the span map is not locked at the time, so this never blocks.
+impl fmt::Debug for SpanDumpLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("SpanDumpLayer");
+        match self.shared.spans.try_read() {
+            Ok(spans) => debug.field("open_spans", &spans.len()),
+            Err(TryLockError::Poisoned(spans)) => {
+                debug.field("open_spans", &spans.into_inner().len())
+            }
+            Err(TryLockError::WouldBlock) => debug.field("open_spans", &format_args!("<locked>")),
+        };
+        debug
+            .field("dispatch_id", &self.dispatch_id.get())
+            .finish_non_exhaustive()
+    }
 }
 
-impl SpanDumpLayer {
-    pub fn new() -> Self {
+impl Clone for SpanDumpLayer {
+    fn clone(&self) -> Self {
         SpanDumpLayer {
-            spans: Arc::new(RwLock::new(Default::default())),
+            shared: self.shared.clone(),
+            dispatch_id: OnceLock::new(),
+            dispatch: OnceLock::new(),
         }
     }
+}
 
-    pub fn snapshot(&self) -> SpanSnapshot {
-        let spans_read = self.spans.read().unwrap();
-        spans_read.clone()
+impl Default for SpanDumpLayer {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl<S: Subscriber> Layer<S> for SpanDumpLayer {
+impl<S> Layer<S> for SpanDumpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        let _ = self
+            .dispatch_id
+            .set(NEXT_DISPATCH_ID.fetch_add(1, Ordering::Relaxed));
+        let _ = self.dispatch.set(subscriber.downgrade());
+    }
+
+    // Returning `false` here, or `never` from `register_callsite`, would
+    // disable the callsite for the layers next to this one too, see the
+    // crate docs.
+    fn register_callsite(&self, _metadata: &'static tracing::Metadata<'static>) -> Interest {
+        Interest::always()
+    }
+
     fn enabled(
         &self,
         metadata: &tracing::Metadata<'_>,
@@ -79,18 +841,66 @@ impl<S: Subscriber> Layer<S> for SpanDumpLayer {
         &self,
         attrs: &span::Attributes<'_>,
         id: &span::Id,
-        _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
     ) {
-        let mut spans_write = self.spans.write().unwrap();
-        spans_write.new_span(attrs, id);
+        let passed = self
+            .shared
+            .config
+            .capture_if
+            .as_ref()
+            .is_none_or(|capture_if| capture_if(attrs.metadata()))
+            && record::has_fields(attrs, &self.shared.config.required_fields);
+        if !passed {
+            Stats::incr(&self.shared.stats.filtered);
+            if !self.shared.config.keep_rejected {
+                return;
+            }
+        }
+        if let Some(rate) = self.shared.config.sample_rate {
+            if !self.shared.stats.sample(rate) {
+                return;
+            }
+        }
+
+        let mut record = self.new_record(attrs, id, &ctx);
+        if !passed {
+            record.filter = FilterDecision::Rejected;
+        }
+        if self.shared.config.capture_on_enter {
+            if let Some(span) = ctx.span(id) {
+                span.extensions_mut().insert(PendingRecord(record));
+            }
+            return;
+        }
+        self.track(record);
     }
 
     fn on_record(
         &self,
-        _span: &span::Id,
-        _values: &span::Record<'_>,
-        _ctx: tracing_subscriber::layer::Context<'_, S>,
+        span: &span::Id,
+        values: &span::Record<'_>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
     ) {
+        if !self.shared.config.capture_fields {
+            return;
+        }
+
+        if self.shared.config.capture_on_enter {
+            if let Some(span) = ctx.span(span) {
+                if let Some(pending) = span.extensions_mut().get_mut::<PendingRecord>() {
+                    let rules = &self.shared.config.field_rules;
+                    values.record(&mut pending.0.fields.capture(rules));
+                    return;
+                }
+            }
+        }
+
+        // Rendered before taking the lock, so a panicking `Debug` impl cannot
+        // poison it.
+        let rules = &self.shared.config.field_rules;
+        let mut fields = SpanFields::default();
+        values.record(&mut fields.capture_uncapped(rules));
+        self.shared.write_spans().record(span, fields, rules);
     }
 
     fn on_follows_from(
@@ -109,20 +919,89 @@ impl<S: Subscriber> Layer<S> for SpanDumpLayer {
         true
     }
 
-    fn on_event(
-        &self,
-        _event: &tracing::Event<'_>,
-        _ctx: tracing_subscriber::layer::Context<'_, S>,
-    ) {
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        // The layer's own lifecycle events are not activity of the span.
+        if self.shared.config.count_events && event.metadata().target() != LIFECYCLE_TARGET {
+            if let Some(span) = ctx.event_span(event) {
+                self.shared.write_spans().record_event(&span.id());
+            }
+        }
+
+        let Some(capture) = &self.shared.config.capture_on_event else {
+            return;
+        };
+        // Records bridged from `log` all share one callsite with target
+        // "log"; match on the record's own target and location instead.
+        #[cfg(feature = "tracing-log")]
+        let normalized = tracing_log::NormalizeEvent::normalized_metadata(event);
+        #[cfg(feature = "tracing-log")]
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        #[cfg(not(feature = "tracing-log"))]
+        let metadata = event.metadata();
+        if !(capture.filter)(metadata) {
+            return;
+        }
+        if let Some(_guard) = guard::ReentrancyGuard::enter() {
+            (capture.callback)(self.snapshot());
+        }
     }
 
-    fn on_enter(&self, _id: &span::Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {}
+    fn on_enter(&self, id: &span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if self.shared.config.capture_on_enter {
+            let pending = ctx
+                .span(id)
+                .and_then(|span| span.extensions_mut().remove::<PendingRecord>());
+            if let Some(PendingRecord(record)) = pending {
+                self.track(record);
+            }
+        }
+
+        if self.shared.config.track_enters {
+            let now = self.shared.config.now();
+            let depth = ctx.span_scope(id).map_or(0, |scope| scope.count());
+            self.shared.write_spans().enter(id, now, depth);
+        } else if let Some(span) = ctx.span(id) {
+            let first = span.extensions_mut().replace(Entered).is_none();
+            if first {
+                self.shared.write_spans().mark_entered(id);
+            }
+        }
+    }
 
-    fn on_exit(&self, _id: &span::Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {}
+    fn on_exit(&self, id: &span::Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if self.shared.config.track_enters {
+            let now = self.shared.config.now();
+            self.shared.write_spans().exit(id, now);
+        }
+    }
 
     fn on_close(&self, id: span::Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {
-        let mut spans_write = self.spans.write().unwrap();
-        spans_write.close_span(id);
+        let closed_at = self.shared.config.now();
+        let (closed, count) = {
+            let mut spans_write = self.shared.write_spans();
+            let closed = spans_write
+                .close_span(id)
+                .map(|record| SpanLifetime { record, closed_at });
+            if let Some(lifetime) = &closed {
+                self.retain_child(&spans_write, lifetime);
+            }
+            (closed, spans_write.len())
+        };
+
+        if let Some(lifetime) = closed {
+            let record = &lifetime.record;
+            self.check_threshold(count);
+            self.shared.name_limits.closed(record.meta.name);
+            self.log_lifecycle("span closed", &record.id, record.meta.name);
+            self.shared
+                .subscribers
+                .publish(&self.shared.stats, || SpanEvent::Closed(record.id.clone()));
+
+            #[cfg(feature = "hdrhistogram")]
+            self.record_latency(&lifetime);
+            self.retain_recent(&lifetime);
+            self.retain_closed(lifetime);
+        }
     }
 
     fn on_id_change(
@@ -136,6 +1015,8 @@ impl<S: Subscriber> Layer<S> for SpanDumpLayer {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use futures::FutureExt;
     use tracing::{info_span, Instrument};
     use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
@@ -143,6 +1024,65 @@ mod tests {
 
     use super::*;
 
+    #[derive(Clone, Default)]
+    struct SpanNames(Arc<Mutex<Vec<&'static str>>>);
+
+    impl<S: Subscriber> Layer<S> for SpanNames {
+        fn on_new_span(
+            &self,
+            attrs: &span::Attributes<'_>,
+            _id: &span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            self.0.lock().unwrap().push(attrs.metadata().name());
+        }
+    }
+
+    #[test]
+    fn test_other_layer_filters() {
+        use tracing_subscriber::filter::LevelFilter;
+
+        let span_dumper = SpanDumpLayer::builder()
+            .capture_if(|meta| meta.name() != "skipped")
+            .build();
+        let names = SpanNames::default();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .with(names.clone().with_filter(LevelFilter::INFO))
+            .set_default();
+
+        let _info = info_span!("info");
+        let _debug = tracing::debug_span!("debug");
+        let _skipped = info_span!("skipped");
+
+        assert_eq!(*names.0.lock().unwrap(), ["info", "skipped"]);
+        let captured: Vec<_> = span_dumper.snapshot().names().into_iter().collect();
+        assert_eq!(captured, ["debug", "info"]);
+    }
+
+    #[test]
+    fn test_read_your_writes() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        for _ in 0..100 {
+            let s = info_span!("test");
+            assert!(span_dumper.snapshot().get(&s.id().unwrap()).is_some());
+        }
+
+        let dispatch = tracing::dispatcher::get_default(Dispatch::clone);
+        let s = std::thread::spawn(move || {
+            tracing::dispatcher::with_default(&dispatch, || info_span!("spawned"))
+        })
+        .join()
+        .unwrap();
+        assert!(span_dumper.snapshot().get(&s.id().unwrap()).is_some());
+    }
+
     #[test]
     fn test_with_span() {
         let span_dumper = SpanDumpLayer::new();
@@ -326,4 +1266,1263 @@ mod tests {
             .collect::<Vec<_>>();
         assert_eq!(spans.len(), 0);
     }
+
+    /// Events with this target make [`DuplicateNewSpan`] deliver
+    /// `on_new_span` for their span once more.
+    const REDELIVER: &str = "redeliver";
+
+    /// Forwards `on_new_span` to the inner layer twice, like a misbehaving
+    /// subscriber stack, and again for the span of any event targeting
+    /// [`REDELIVER`].
+    struct DuplicateNewSpan(SpanDumpLayer);
+
+    impl<S> Layer<S> for DuplicateNewSpan
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &span::Attributes<'_>,
+            id: &span::Id,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            self.0.on_new_span(attrs, id, ctx.clone());
+            self.0.on_new_span(attrs, id, ctx);
+        }
+
+        fn on_record(
+            &self,
+            span: &span::Id,
+            values: &span::Record<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            self.0.on_record(span, values, ctx);
+        }
+
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            self.0.on_event(event, ctx.clone());
+            if event.metadata().target() != REDELIVER {
+                return;
+            }
+            if let Some(span) = ctx.event_span(event) {
+                let metadata = span.metadata();
+                let values = metadata.fields().value_set(&[]);
+                let attrs = span::Attributes::new_root(metadata, &values);
+                self.0.on_new_span(&attrs, &span.id(), ctx.clone());
+            }
+        }
+
+        fn on_enter(&self, id: &span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+            self.0.on_enter(id, ctx);
+        }
+
+        fn on_exit(&self, id: &span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+            self.0.on_exit(id, ctx);
+        }
+
+        fn on_close(&self, id: span::Id, ctx: tracing_subscriber::layer::Context<'_, S>) {
+            self.0.on_close(id, ctx);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_new_span_merges() {
+        let span_dumper = SpanDumpLayer::builder()
+            .capture_fields(true)
+            .track_enters(true)
+            .count_events(true)
+            .build();
+        let events = span_dumper.subscribe();
+
+        let _sub = tracing_subscriber::registry()
+            .with(DuplicateNewSpan(span_dumper.clone()))
+            .set_default();
+
+        let first = info_span!("first");
+        let s = info_span!("test", initial = 1, late = tracing::field::Empty);
+        let opened_at = span_dumper
+            .snapshot()
+            .get(&s.id().unwrap())
+            .unwrap()
+            .opened_at;
+        s.record("late", "recorded");
+
+        // A duplicate delivery of the same id after the span was entered and
+        // emitted an event.
+        s.in_scope(|| {
+            tracing::info!("working");
+            tracing::info!(target: REDELIVER, "again");
+        });
+
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        let record = snapshot.get(&s.id().unwrap()).unwrap();
+        assert_eq!(record.opened_at, opened_at);
+        assert_eq!(
+            record.seq,
+            snapshot.get(&first.id().unwrap()).unwrap().seq + 1
+        );
+        assert_eq!(record.parent_kind, ParentKind::Root);
+        assert_eq!(
+            record.fields.iter().collect::<Vec<_>>(),
+            [("initial", "1"), ("late", "recorded")]
+        );
+        assert!(record.was_entered);
+        assert_eq!(record.timing.enter_count, 1);
+        assert_eq!(record.event_count(), 2);
+
+        assert_eq!(span_dumper.stats().tracked_count, 2);
+        let opened = events
+            .try_iter()
+            .filter(|event| matches!(event, SpanEvent::Opened(_)))
+            .count();
+        assert_eq!(opened, 2);
+
+        drop(s);
+        assert_eq!(span_dumper.count(), 1);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_snapshot_async() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let spans = (0..600).map(|i| info_span!("test", i)).collect::<Vec<_>>();
+        let closed = info_span!("closed");
+        drop(closed);
+
+        let snapshot = span_dumper.snapshot_async().await;
+        assert_eq!(snapshot.len(), 600);
+        assert_eq!(
+            snapshot.open_spans_by_creation(),
+            span_dumper.snapshot().open_spans_by_creation()
+        );
+
+        drop(spans);
+    }
+
+    #[cfg(feature = "hdrhistogram")]
+    #[test]
+    fn test_latency_histogram() {
+        let span_dumper = SpanDumpLayer::builder().latency_histograms(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        for _ in 0..5 {
+            let _s = info_span!("query").entered();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let _open = info_span!("open").entered();
+
+        let histogram = span_dumper.latency_histogram("query").unwrap();
+        assert_eq!(histogram.len(), 5);
+        assert!(histogram.min() >= 1000);
+        assert!(span_dumper.latency_histogram("open").is_none());
+    }
+
+    #[cfg(feature = "hdrhistogram")]
+    #[test]
+    fn test_duration_percentile() {
+        let now = Arc::new(Mutex::new(std::time::Instant::now()));
+        let span_dumper = SpanDumpLayer::builder()
+            .latency_histograms(true)
+            .with_clock({
+                let now = now.clone();
+                move || *now.lock().unwrap()
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        for millis in 1..=100 {
+            let _s = info_span!("db.query");
+            *now.lock().unwrap() += Duration::from_millis(millis);
+        }
+
+        let p = |q| span_dumper.duration_percentile("db.query", q).unwrap();
+        let close_to = |actual: Duration, millis: u64| {
+            let expected = Duration::from_millis(millis);
+            actual >= expected && actual - expected <= expected / 1000
+        };
+        assert!(close_to(p(0.5), 50), "{:?}", p(0.5));
+        assert!(close_to(p(0.99), 99), "{:?}", p(0.99));
+        assert!(close_to(p(1.0), 100), "{:?}", p(1.0));
+        assert_eq!(span_dumper.duration_percentile("db.query", 1.5), None);
+        assert_eq!(span_dumper.duration_percentile("missing", 0.5), None);
+    }
+
+    #[test]
+    fn test_record_transform() {
+        let span_dumper = SpanDumpLayer::builder()
+            .capture_fields(true)
+            .with_record_transform(|record| {
+                if let Some(target) = record.meta.target.strip_prefix("my_app::") {
+                    record.meta.target = target;
+                }
+                record.class = record.field_str("kind").map(|kind| kind.len() as u32);
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let s = info_span!(target: "my_app::db", "query", kind = "read");
+
+        let snapshot = span_dumper.snapshot();
+        let record = snapshot.get(&s.id().unwrap()).unwrap();
+        assert_eq!(record.meta.target, "db");
+        assert_eq!(record.class, Some(4));
+    }
+
+    #[test]
+    fn test_max_open_spans() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let a = info_span!("a");
+        let b = info_span!("b");
+        let _c = info_span!("c");
+        drop((a, b));
+        assert_eq!(span_dumper.max_open_spans(), 3);
+
+        span_dumper.reset_high_water();
+        assert_eq!(span_dumper.max_open_spans(), 1);
+        let _d = info_span!("d");
+        assert_eq!(span_dumper.max_open_spans(), 2);
+    }
+
+    #[test]
+    fn test_drain() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let a = info_span!("a");
+        let b = info_span!("b");
+        let drained = span_dumper.drain();
+        assert_eq!(drained.len(), 2);
+        assert!(drained.taken_at().is_some());
+        assert_eq!(span_dumper.count(), 0);
+
+        drop(a);
+        let c = info_span!("c");
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        let seq = |snapshot: &SpanSnapshot, span: &tracing::Span| {
+            snapshot.get(&span.id().unwrap()).unwrap().seq
+        };
+        assert!(seq(&snapshot, &c) > seq(&drained, &b));
+    }
+
+    #[test]
+    fn test_require_field() {
+        let span_dumper = SpanDumpLayer::builder().require_field("request_id").build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let request = info_span!("request", request_id = 7);
+        let _plain = info_span!("plain");
+        let late = info_span!("late", request_id = tracing::field::Empty);
+        late.record("request_id", 8);
+
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert!(snapshot.get(&request.id().unwrap()).is_some());
+        assert_eq!(span_dumper.stats().filtered_count, 2);
+    }
+
+    #[test]
+    fn test_keep_rejected() {
+        let span_dumper = SpanDumpLayer::builder()
+            .capture_if(|metadata| metadata.target().starts_with("db"))
+            .keep_rejected(true)
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let query = info_span!(target: "db", "query");
+        let request = info_span!(target: "http", "request");
+
+        let snapshot = span_dumper.snapshot();
+        let decision = |span: &tracing::Span| snapshot.get(&span.id().unwrap()).unwrap().filter;
+        assert_eq!(decision(&query), FilterDecision::Passed);
+        assert_eq!(decision(&request), FilterDecision::Rejected);
+        assert_eq!(span_dumper.stats().filtered_count, 1);
+
+        let mut out = Vec::new();
+        snapshot.dump_verbose_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("query id=") && out.contains("  filter: passed\n"));
+        assert!(out.contains("  filter: rejected\n"));
+
+        let unfiltered = SpanDumpLayer::new();
+        let _sub = tracing_subscriber::registry()
+            .with(unfiltered.clone())
+            .set_default();
+        let plain = info_span!("plain");
+        let record = unfiltered.snapshot().get(&plain.id().unwrap()).cloned();
+        assert_eq!(record.unwrap().filter, FilterDecision::Unfiltered);
+    }
+
+    #[test]
+    fn test_capture_if_stats() {
+        let span_dumper = SpanDumpLayer::builder()
+            .capture_if(|metadata| metadata.target().starts_with("db"))
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _query = info_span!(target: "db", "query");
+        let request = info_span!(target: "http", "request");
+        let _other = info_span!(target: "http", "other");
+        drop(request);
+
+        assert_eq!(span_dumper.count(), 1);
+        assert_eq!(
+            span_dumper.stats(),
+            LayerStats {
+                filtered_count: 2,
+                tracked_count: 1,
+                sampled_out_count: 0,
+                dropped_events: 0,
+                write_lock_contended: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let span_dumper = SpanDumpLayer::builder()
+            .track_enters(true)
+            .sample_rate(0.25)
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let spans: Vec<_> = (0..8).map(|i| info_span!("request", i)).collect();
+        for span in &spans {
+            span.in_scope(|| {});
+        }
+        assert_eq!(span_dumper.count(), 2);
+        let stats = span_dumper.stats();
+        assert_eq!((stats.tracked_count, stats.sampled_out_count), (2, 6));
+
+        drop(spans);
+        assert_eq!(span_dumper.count(), 0);
+        assert!(SpanDumpLayer::builder()
+            .sample_rate(1.5)
+            .build()
+            .shared
+            .config
+            .sample_rate
+            .is_none());
+    }
+
+    #[test]
+    fn test_capture_fields() {
+        let span_dumper = SpanDumpLayer::builder().capture_fields(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let s = info_span!(
+            "test",
+            user = "alice",
+            attempt = 1,
+            late = tracing::field::Empty
+        );
+        s.record("late", true);
+        s.record("attempt", 2);
+
+        let snapshot = span_dumper.snapshot();
+        let fields = &snapshot.get(&s.id().unwrap()).unwrap().fields;
+        assert_eq!(
+            fields.iter().collect::<Vec<_>>(),
+            [("user", "alice"), ("attempt", "2"), ("late", "true")]
+        );
+    }
+
+    #[test]
+    fn test_redact_fields() {
+        struct Secret;
+
+        impl std::fmt::Debug for Secret {
+            fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                panic!("redacted values must not be formatted")
+            }
+        }
+
+        let span_dumper = SpanDumpLayer::builder()
+            .capture_fields(true)
+            .redact_fields(&["password", "token"])
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let s = info_span!(
+            "login",
+            user = "alice",
+            password = ?Secret,
+            token = tracing::field::Empty,
+            token_kind = "bearer"
+        );
+        s.record("token", "abc123");
+
+        let snapshot = span_dumper.snapshot();
+        let fields = &snapshot.get(&s.id().unwrap()).unwrap().fields;
+        assert_eq!(
+            fields.iter().collect::<Vec<_>>(),
+            [
+                ("user", "alice"),
+                ("password", REDACTED),
+                ("token_kind", "bearer"),
+                ("token", REDACTED)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_field_limits() {
+        let span_dumper = SpanDumpLayer::builder()
+            .capture_fields(true)
+            .max_field_len(2)
+            .max_fields_per_span(2)
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let s = info_span!(
+            "request",
+            path = "héllo",
+            status = tracing::field::Empty,
+            method = "GET"
+        );
+        s.record("status", 404);
+        s.record("path", "ab");
+
+        let snapshot = span_dumper.snapshot();
+        let fields = &snapshot.get(&s.id().unwrap()).unwrap().fields;
+        assert_eq!(
+            fields.iter().collect::<Vec<_>>(),
+            [("path", "ab"), ("method", "GE…")]
+        );
+
+        s.record("path", "héllo");
+        let snapshot = span_dumper.snapshot();
+        let fields = &snapshot.get(&s.id().unwrap()).unwrap().fields;
+        assert_eq!(fields.get("path"), Some(&*format!("h{TRUNCATED}")));
+    }
+
+    #[test]
+    fn test_panicking_debug_field() {
+        struct Panics;
+
+        impl std::fmt::Debug for Panics {
+            fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                panic!("bad Debug impl")
+            }
+        }
+
+        let span_dumper = SpanDumpLayer::builder().capture_fields(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let s = info_span!("request", late = tracing::field::Empty);
+        let recorded = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            s.record("late", tracing::field::debug(Panics));
+        }));
+        assert!(recorded.is_err());
+        assert!(!span_dumper.shared.spans.is_poisoned());
+
+        let next = info_span!("next");
+        drop(s);
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(snapshot.names(), std::collections::BTreeSet::from(["next"]));
+        assert!(snapshot.get(&next.id().unwrap()).unwrap().fields.is_empty());
+    }
+
+    #[test]
+    fn test_postmortem() {
+        let span_dumper = SpanDumpLayer::builder()
+            .capture_fields(true)
+            .postmortem(2)
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        for i in 0..3 {
+            let _s = info_span!("request", i).entered();
+        }
+        let _open = info_span!("open").entered();
+
+        let postmortem = span_dumper.postmortem();
+        assert_eq!(postmortem.len(), 2);
+        assert_eq!(postmortem[0].record.fields.get("i"), Some("1"));
+        assert_eq!(postmortem[1].record.fields.get("i"), Some("2"));
+        assert!(postmortem[1].closed_at >= postmortem[1].record.opened_at);
+    }
+
+    #[test]
+    fn test_retain_closed_children() {
+        let span_dumper = SpanDumpLayer::builder().retain_closed_children(2).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!(target: "app", "root");
+        let child = info_span!(target: "app", parent: &root, "child");
+        drop(info_span!(target: "app", parent: &child, "leaf"));
+        drop(child);
+        drop(info_span!(target: "app", parent: &root, "dropped"));
+
+        assert_eq!(span_dumper.count(), 1);
+        let snapshot = span_dumper.snapshot_with_closed();
+        let options = DumpOptions {
+            stable_ids: true,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        snapshot
+            .dump_to_with(&mut out, DumpFormat::Tree, &options)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "root (app) id=#1\n  child (app) id=#2 closed\n    leaf (app) id=#3 closed\n"
+        );
+
+        drop(root);
+        assert_eq!(span_dumper.snapshot_with_closed().len(), 0);
+    }
+
+    #[test]
+    fn test_retain_recent_per_name() {
+        let span_dumper = SpanDumpLayer::builder()
+            .capture_fields(true)
+            .retain_recent_per_name(2)
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        for i in 0..3 {
+            drop(info_span!("request", i));
+        }
+        drop(info_span!("query"));
+        let _open = info_span!("open");
+
+        let requests: Vec<_> = span_dumper
+            .recent_for_name("request")
+            .iter()
+            .map(|closed| closed.record.fields.get("i").unwrap().to_string())
+            .collect();
+        assert_eq!(requests, ["1", "2"]);
+        assert_eq!(span_dumper.recent_for_name("query").len(), 1);
+        assert!(span_dumper.recent_for_name("open").is_empty());
+        assert!(span_dumper.postmortem().is_empty());
+    }
+
+    #[test]
+    fn test_postmortem_disabled_by_default() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let s = info_span!("test", field = 1);
+        let id = s.id().unwrap();
+        assert!(span_dumper.snapshot().get(&id).unwrap().fields.is_empty());
+        drop(s);
+
+        assert!(span_dumper.postmortem().is_empty());
+    }
+
+    #[test]
+    fn test_with_clock() {
+        let start = std::time::Instant::now();
+        let now = Arc::new(Mutex::new(start));
+        let span_dumper = SpanDumpLayer::builder()
+            .postmortem(1)
+            .with_clock({
+                let now = now.clone();
+                move || *now.lock().unwrap()
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let old = info_span!("old");
+        *now.lock().unwrap() += Duration::from_secs(5);
+        let _new = info_span!("new");
+
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(snapshot.taken_at(), Some(start + Duration::from_secs(5)));
+        let old_record = snapshot.get(&old.id().unwrap()).unwrap();
+        assert_eq!(snapshot.age_of(old_record), Duration::from_secs(5));
+
+        let filter = SnapshotFilter::new().min_age(Duration::from_secs(5));
+        let names: Vec<_> = snapshot
+            .filtered(&filter)
+            .open_spans()
+            .map(|span| span.meta.name)
+            .collect();
+        assert_eq!(names, ["old"]);
+
+        *now.lock().unwrap() += Duration::from_secs(1);
+        drop(old);
+        assert_eq!(
+            span_dumper.postmortem()[0].duration(),
+            Duration::from_secs(6)
+        );
+    }
+
+    #[test]
+    fn test_busy_and_idle() {
+        let now = Arc::new(Mutex::new(std::time::Instant::now()));
+        let advance = |secs| *now.lock().unwrap() += Duration::from_secs(secs);
+        let span_dumper = SpanDumpLayer::builder()
+            .track_enters(true)
+            .postmortem(1)
+            .with_clock({
+                let now = now.clone();
+                move || *now.lock().unwrap()
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let span = info_span!("work");
+        advance(1);
+        span.in_scope(|| {
+            let _nested = span.enter();
+            advance(2);
+        });
+        advance(3);
+        let entered = span.enter();
+        advance(4);
+
+        let snapshot = span_dumper.snapshot();
+        let record = snapshot.get(&span.id().unwrap()).unwrap();
+        assert!(record.timing.is_entered());
+        assert_eq!(record.timing.enter_count, 3);
+        assert_eq!(record.timing.max_enter_depth, 1);
+        let taken_at = snapshot.taken_at().unwrap();
+        assert_eq!(record.busy_duration_at(taken_at), Duration::from_secs(6));
+        assert_eq!(record.idle_duration_at(taken_at), Duration::from_secs(4));
+        // Without an explicit time the record measures with the same clock.
+        advance(1);
+        assert_eq!(record.age(), Duration::from_secs(11));
+        assert_eq!(record.busy_duration(), Duration::from_secs(7));
+        assert_eq!(record.idle_duration(), Duration::from_secs(4));
+        assert!(SnapshotFilter::new()
+            .min_age(Duration::from_secs(11))
+            .matches_age(record));
+        *now.lock().unwrap() -= Duration::from_secs(1);
+
+        drop(entered);
+        advance(5);
+        drop(span);
+        let closed = &span_dumper.postmortem()[0];
+        assert_eq!(closed.busy_duration(), Duration::from_secs(6));
+        assert_eq!(closed.idle_duration(), Duration::from_secs(9));
+    }
+
+    #[test]
+    fn test_hot_path() {
+        let span_dumper = SpanDumpLayer::builder().track_enters(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _parked = info_span!("parked");
+        let names =
+            |path: Vec<SpanRecord>| path.iter().map(|span| span.meta.name).collect::<Vec<_>>();
+        assert!(span_dumper.hot_path().is_empty());
+
+        let _request = info_span!("request").entered();
+        let _idle_child = info_span!("idle_child");
+        let _handler = info_span!("handler").entered();
+        let _query = info_span!("query").entered();
+        assert_eq!(
+            names(span_dumper.hot_path()),
+            ["request", "handler", "query"]
+        );
+    }
+
+    #[test]
+    fn test_active_snapshot() {
+        let span_dumper = SpanDumpLayer::builder().track_enters(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _parked = info_span!("parked");
+        let exited = info_span!("exited");
+        exited.in_scope(|| {});
+        let _running = info_span!("running").entered();
+
+        let active = span_dumper.active_snapshot();
+        let names: Vec<_> = active.open_spans().map(|span| span.meta.name).collect();
+        assert_eq!(names, ["running"]);
+        assert_eq!(span_dumper.count(), 3);
+    }
+
+    #[test]
+    fn test_capture_on_enter() {
+        fn open_names(capture_on_enter: bool) -> Vec<&'static str> {
+            let span_dumper = SpanDumpLayer::builder()
+                .capture_on_enter(capture_on_enter)
+                .capture_fields(true)
+                .build();
+
+            let _sub = tracing_subscriber::registry()
+                .with(span_dumper.clone())
+                .set_default();
+
+            let _unpolled = async {}.instrument(info_span!("unpolled"));
+            let entered = info_span!("entered", late = tracing::field::Empty);
+            entered.record("late", 1);
+            entered.in_scope(|| {});
+
+            let snapshot = span_dumper.snapshot();
+            let record = snapshot.get(&entered.id().unwrap()).unwrap();
+            assert_eq!(record.fields.get("late"), Some("1"));
+            let mut names: Vec<_> = snapshot.open_spans().map(|span| span.meta.name).collect();
+            names.sort();
+            names
+        }
+
+        assert_eq!(open_names(false), ["entered", "unpolled"]);
+        assert_eq!(open_names(true), ["entered"]);
+    }
+
+    #[test]
+    fn test_debug() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _spans = [
+            info_span!("request"),
+            info_span!("request"),
+            info_span!("db"),
+        ];
+        assert_eq!(
+            format!("{:?}", span_dumper),
+            "SpanDumpLayer { open_spans: 3, dispatch_id: None, .. }"
+        );
+        assert_eq!(
+            format!("{:?}", span_dumper.snapshot()),
+            r#"SpanSnapshot { len: 3, by_name: {"request": 2, "db": 1} }"#
+        );
+
+        let _write = span_dumper.shared.spans.write().unwrap();
+        assert!(format!("{:?}", span_dumper).contains("open_spans: <locked>"));
+    }
+
+    #[test]
+    fn test_poisoned_span_map() {
+        let span_dumper = SpanDumpLayer::builder().track_enters(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let open = info_span!("open");
+        let _ = std::thread::scope(|s| {
+            s.spawn(|| {
+                let _write = span_dumper.shared.write_spans();
+                panic!("poison the span map");
+            })
+            .join()
+        });
+        assert!(span_dumper.shared.spans.is_poisoned());
+
+        let next = info_span!("next");
+        next.in_scope(|| {});
+        drop(open);
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert!(snapshot.get(&next.id().unwrap()).unwrap().was_entered);
+        assert_eq!(span_dumper.count(), 1);
+    }
+
+    #[test]
+    fn test_backfill_from() {
+        type BoxedLayer = Box<dyn Layer<tracing_subscriber::Registry> + Send + Sync>;
+        let identity: BoxedLayer = Box::new(tracing_subscriber::layer::Identity::new());
+        let (layer, handle) = tracing_subscriber::reload::Layer::new(identity);
+        let _sub = tracing_subscriber::registry().with(layer).set_default();
+
+        let outer = info_span!("outer");
+        let _outer = outer.enter();
+        let inner = info_span!("inner");
+        let _inner = inner.enter();
+        let _idle = info_span!("idle");
+
+        let span_dumper = SpanDumpLayer::new();
+        handle
+            .reload(Box::new(span_dumper.clone()) as BoxedLayer)
+            .unwrap();
+        let dispatch = tracing::dispatcher::get_default(Dispatch::clone);
+
+        assert_eq!(
+            span_dumper.backfill_from::<tracing_subscriber::Registry>(&dispatch),
+            2
+        );
+        assert_eq!(
+            span_dumper.backfill_from::<tracing_subscriber::Registry>(&dispatch),
+            0
+        );
+
+        let snapshot = span_dumper.snapshot();
+        let outer_id = outer.id().unwrap();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot.validate(), []);
+        assert_eq!(
+            snapshot.get(&outer_id).unwrap().parent_kind,
+            ParentKind::Root
+        );
+        assert_eq!(
+            snapshot.get(&inner.id().unwrap()).unwrap().parent_kind,
+            ParentKind::Contextual(outer_id)
+        );
+    }
+
+    #[test]
+    fn test_lock_contention() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let s = info_span!("request");
+        let id = s.id().unwrap();
+        assert!(!span_dumper.is_locked());
+
+        let write = span_dumper.shared.spans.write().unwrap();
+        assert!(span_dumper.is_locked());
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| span_dumper.with_record_mut(&id, |_| ()));
+            while span_dumper.stats().write_lock_contended == 0 {
+                std::thread::yield_now();
+            }
+            drop(write);
+            assert_eq!(waiter.join().unwrap(), Some(()));
+        });
+        assert_eq!(span_dumper.stats().write_lock_contended, 1);
+    }
+
+    #[test]
+    fn test_snapshot_into() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let mut buf = SpanSnapshot::default();
+        let first = info_span!("first");
+        span_dumper.snapshot_into(&mut buf);
+        assert_eq!(buf.len(), 1);
+        assert!(buf.taken_at().is_some());
+
+        drop(first);
+        let second = info_span!("second");
+        span_dumper.snapshot_into(&mut buf);
+        let names: Vec<_> = buf.open_spans().map(|span| span.meta.name).collect();
+        assert_eq!(names, ["second"]);
+        assert_eq!(buf.get(&second.id().unwrap()).unwrap().seq, 1);
+
+        buf.clear();
+        assert!(buf.is_empty() && buf.taken_at().is_none());
+    }
+
+    #[test]
+    fn test_with_record_mut() {
+        #[derive(Debug, PartialEq)]
+        struct Owner(&'static str);
+
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let span = info_span!("annotated");
+        let id = span.id().unwrap();
+        let before = span_dumper.snapshot();
+        assert_eq!(
+            span_dumper.with_record_mut(&id, |record| record.extensions.insert(Owner("db"))),
+            Some(None)
+        );
+
+        let snapshot = span_dumper.snapshot();
+        let record = snapshot.get(&id).unwrap();
+        assert_eq!(record.extensions.get::<Owner>(), Some(&Owner("db")));
+        assert_eq!(record.extensions.get::<u32>(), None);
+        assert!(before.get(&id).unwrap().extensions.is_empty());
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            span_dumper.with_record_mut(&id, |record| {
+                record.extensions.insert(Owner("cache"));
+                panic!("annotation failed");
+            })
+        }));
+        assert!(panicked.is_err());
+        let next = info_span!("next");
+        let snapshot = span_dumper.snapshot();
+        assert!(snapshot.get(&next.id().unwrap()).is_some());
+        let record = snapshot.get(&id).unwrap();
+        assert_eq!(record.extensions.get::<Owner>(), Some(&Owner("cache")));
+
+        drop(span);
+        assert!(span_dumper.with_record_mut(&id, |_| ()).is_none());
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        span_dumper.checkpoint("start");
+        let _s = info_span!("request");
+        let diff = span_dumper.diff_from_checkpoint("start").unwrap();
+        assert_eq!(diff.opened[0].meta.name, "request");
+        assert!(matches!(
+            span_dumper.diff_from_checkpoint("missing"),
+            Err(SpanDumpError::NotFound(name)) if name == "missing"
+        ));
+
+        for i in 0..MAX_CHECKPOINTS {
+            span_dumper.checkpoint(&i.to_string());
+        }
+        assert!(span_dumper.diff_from_checkpoint("start").is_err());
+        assert!(span_dumper.diff_from_checkpoint("0").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dispatch_id() {
+        let span_dumper = SpanDumpLayer::new();
+        assert_eq!(span_dumper.dispatch_id(), None);
+
+        let spawn = |name: &'static str| {
+            let layer = span_dumper.clone();
+            std::thread::spawn(move || {
+                let _sub = tracing_subscriber::registry().with(layer).set_default();
+                let span = info_span!("worker", name);
+                let id = span.id().unwrap();
+                std::mem::forget(span);
+                id
+            })
+        };
+        let first = spawn("first").join().unwrap();
+        let second = spawn("second").join().unwrap();
+
+        let snapshot = span_dumper.snapshot();
+        let first = snapshot.get(&first).unwrap().dispatch_id;
+        let second = snapshot.get(&second).unwrap().dispatch_id;
+        assert!(first.is_some() && second.is_some());
+        assert_ne!(first, second);
+    }
+
+    /// Collects the `message` and `name` fields of lifecycle events.
+    #[derive(Clone, Default)]
+    struct LifecycleEvents(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> Layer<S> for LifecycleEvents {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if event.metadata().target() != LIFECYCLE_TARGET {
+                return;
+            }
+            let mut fields = SpanFields::default();
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(format!(
+                "{} name={}",
+                fields.get("message").unwrap(),
+                fields.get("name").unwrap()
+            ));
+        }
+    }
+
+    #[test]
+    fn test_log_lifecycle() {
+        let captures = Arc::new(Mutex::new(0));
+        let events = LifecycleEvents::default();
+        let span_dumper = SpanDumpLayer::builder()
+            .log_lifecycle(tracing::Level::INFO)
+            .capture_on_event(|_| true, {
+                let captures = captures.clone();
+                move |_| *captures.lock().unwrap() += 1
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .with(events.clone())
+            .set_default();
+
+        drop(info_span!("request"));
+
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            ["span opened name=request", "span closed name=request"]
+        );
+        assert_eq!(*captures.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_capture_on_event() {
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let span_dumper = SpanDumpLayer::builder()
+            .capture_on_event(|meta| *meta.level() == tracing::Level::ERROR, {
+                let captured = captured.clone();
+                move |snapshot: SpanSnapshot| {
+                    tracing::error!("inside callback");
+                    let names: Vec<_> = snapshot.open_spans().map(|span| span.meta.name).collect();
+                    captured.lock().unwrap().push(names);
+                }
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _s = info_span!("request").entered();
+        tracing::info!("ignored");
+        tracing::error!("failed");
+
+        assert_eq!(*captured.lock().unwrap(), [["request"]]);
+        assert_eq!(span_dumper.with_snapshot(SpanSnapshot::len), 1);
+    }
+
+    #[test]
+    fn test_where_am_i() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        assert_eq!(span_dumper.where_am_i(), "<none>");
+
+        let request = info_span!("request");
+        let _db = info_span!(parent: &request, "db").entered();
+        let _query = info_span!("query").entered();
+        assert_eq!(span_dumper.where_am_i(), "request.db.query");
+    }
+
+    #[cfg(feature = "tracing-log")]
+    #[test]
+    fn test_capture_on_bridged_log() {
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let span_dumper = SpanDumpLayer::builder()
+            .track_enters(true)
+            .capture_on_event(|meta| meta.target() == "app::db", {
+                let captured = captured.clone();
+                move |snapshot: SpanSnapshot| {
+                    let entered: Vec<_> = snapshot
+                        .open_spans()
+                        .filter(|span| span.timing.is_entered())
+                        .map(|span| span.meta.name)
+                        .collect();
+                    captured.lock().unwrap().push(entered);
+                }
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _idle = info_span!("idle");
+        let _s = info_span!("request").entered();
+        let log = |target| {
+            tracing_log::format_trace(
+                &log::Record::builder()
+                    .target(target)
+                    .level(log::Level::Warn)
+                    .args(format_args!("slow query"))
+                    .build(),
+            )
+            .unwrap()
+        };
+        log("app::db");
+        log("app::http");
+
+        assert_eq!(*captured.lock().unwrap(), [["request"]]);
+    }
+
+    #[test]
+    fn test_threshold_exceeded() {
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let span_dumper = SpanDumpLayer::builder()
+            .on_threshold_exceeded(2, {
+                let fired = fired.clone();
+                move |count| fired.lock().unwrap().push(count)
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let mut spans = (0..4).map(|_| info_span!("test")).collect::<Vec<_>>();
+        assert_eq!(span_dumper.count(), 4);
+        assert_eq!(*fired.lock().unwrap(), [3]);
+
+        // Dropping to the limit does not re-arm.
+        spans.truncate(2);
+        spans.push(info_span!("test"));
+        assert_eq!(*fired.lock().unwrap(), [3]);
+
+        spans.truncate(1);
+        spans.push(info_span!("test"));
+        spans.push(info_span!("test"));
+        assert_eq!(*fired.lock().unwrap(), [3, 3]);
+    }
+
+    #[test]
+    fn test_max_spans_for_name() {
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let span_dumper = SpanDumpLayer::builder()
+            .max_spans_for_name("db.connection", 1)
+            .on_name_limit_exceeded({
+                let fired = fired.clone();
+                move |name, count| fired.lock().unwrap().push((name, count))
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _others = [info_span!("other"), info_span!("other")];
+        let mut connections = vec![info_span!("db.connection"), info_span!("db.connection")];
+        connections.push(info_span!("db.connection"));
+        assert_eq!(*fired.lock().unwrap(), [("db.connection", 2)]);
+
+        connections.clear();
+        connections.push(info_span!("db.connection"));
+        connections.push(info_span!("db.connection"));
+        assert_eq!(*fired.lock().unwrap(), [("db.connection", 2); 2]);
+    }
+
+    #[test]
+    fn test_max_spans_for_name_drained() {
+        let layer = Arc::new(OnceLock::<SpanDumpLayer>::new());
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let span_dumper = SpanDumpLayer::builder()
+            .max_spans_for_name("db.connection", 1)
+            .on_name_limit_exceeded({
+                let fired = fired.clone();
+                move |name, count| fired.lock().unwrap().push((name, count))
+            })
+            // Drains right after the first span is stored, before the rest
+            // of its bookkeeping.
+            .on_threshold_exceeded(0, {
+                let layer = layer.clone();
+                move |_| drop(layer.get().unwrap().drain())
+            })
+            .build();
+        layer.set(span_dumper.clone()).unwrap();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _drained = info_span!("db.connection");
+        assert_eq!(span_dumper.count(), 0);
+        let _connections = [info_span!("db.connection"), info_span!("db.connection")];
+        assert_eq!(*fired.lock().unwrap(), [("db.connection", 2)]);
+    }
+
+    #[test]
+    fn test_max_spans_for_name_warns() {
+        let events = LifecycleEvents::default();
+        let span_dumper = SpanDumpLayer::builder()
+            .max_spans_for_name("db.connection", 0)
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .with(events.clone())
+            .set_default();
+
+        let _connection = info_span!("db.connection");
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            ["open spans exceeded the limit for their name name=db.connection"]
+        );
+    }
+
+    #[test]
+    fn test_threshold_callback_can_trace() {
+        let span_dumper = SpanDumpLayer::builder()
+            .on_threshold_exceeded(0, |count| {
+                let _s = info_span!("inside_callback", count).entered();
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _s = info_span!("test").entered();
+        assert_eq!(span_dumper.count(), 1);
+    }
 }
@@ -0,0 +1,102 @@
+//! Breadcrumb events for span open and close, see
+//! [`Builder::log_lifecycle`](crate::Builder::log_lifecycle).
+
+use std::sync::Once;
+
+use tracing::callsite::{Callsite, Identifier};
+use tracing::field::{FieldSet, Value};
+use tracing::metadata::Kind;
+use tracing::subscriber::Interest;
+use tracing::{Dispatch, Event, Level, Metadata};
+
+use crate::guard::ReentrancyGuard;
+
+/// The target of the lifecycle events.
+pub const LIFECYCLE_TARGET: &str = "tracing_span_dump::lifecycle";
+
+const FIELDS: &[&str] = &["message", "id", "name"];
+
+struct LifecycleCallsite(&'static Metadata<'static>);
+
+impl Callsite for LifecycleCallsite {
+    fn set_interest(&self, _interest: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.0
+    }
+}
+
+macro_rules! lifecycle_callsite {
+    ($callsite:ident, $meta:ident, $level:expr) => {
+        static $callsite: LifecycleCallsite = LifecycleCallsite(&$meta);
+        static $meta: Metadata<'static> = Metadata::new(
+            "span lifecycle",
+            LIFECYCLE_TARGET,
+            $level,
+            Some(file!()),
+            Some(line!()),
+            Some(module_path!()),
+            FieldSet::new(FIELDS, Identifier(&$callsite)),
+            Kind::EVENT,
+        );
+    };
+}
+
+lifecycle_callsite!(TRACE_CALLSITE, TRACE_META, Level::TRACE);
+lifecycle_callsite!(DEBUG_CALLSITE, DEBUG_META, Level::DEBUG);
+lifecycle_callsite!(INFO_CALLSITE, INFO_META, Level::INFO);
+lifecycle_callsite!(WARN_CALLSITE, WARN_META, Level::WARN);
+lifecycle_callsite!(ERROR_CALLSITE, ERROR_META, Level::ERROR);
+
+fn metadata(level: Level) -> &'static Metadata<'static> {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        for callsite in [
+            &TRACE_CALLSITE,
+            &DEBUG_CALLSITE,
+            &INFO_CALLSITE,
+            &WARN_CALLSITE,
+            &ERROR_CALLSITE,
+        ] {
+            tracing::callsite::register(callsite);
+        }
+    });
+
+    match level {
+        Level::TRACE => &TRACE_META,
+        Level::DEBUG => &DEBUG_META,
+        Level::INFO => &INFO_META,
+        Level::WARN => &WARN_META,
+        Level::ERROR => &ERROR_META,
+    }
+}
+
+/// Sends a lifecycle event straight to `dispatch`. The event is dropped if
+/// the thread is already inside layer-invoked code.
+///
+/// The event cannot go through the `tracing` macros here: they look up the
+/// thread's current dispatcher, which `tracing` hides while a span is being
+/// created.
+pub(crate) fn emit(dispatch: &Dispatch, level: Level, message: &str, id: u64, name: &str) {
+    let meta = metadata(level);
+    if !dispatch.enabled(meta) {
+        return;
+    }
+    let Some(_guard) = ReentrancyGuard::enter() else {
+        return;
+    };
+
+    let fields = meta.fields();
+    let mut iter = fields.iter();
+    let (message_field, id_field, name_field) = (
+        iter.next().unwrap(),
+        iter.next().unwrap(),
+        iter.next().unwrap(),
+    );
+    let values = [
+        (&message_field, Some(&message as &dyn Value)),
+        (&id_field, Some(&id as &dyn Value)),
+        (&name_field, Some(&name as &dyn Value)),
+    ];
+    dispatch.event(&Event::new(meta, &fields.value_set(&values)));
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Open span counts for the names given to
+/// [`Builder::max_spans_for_name`](crate::Builder::max_spans_for_name).
+/// Spans with other names are not counted.
+#[derive(Default)]
+pub(crate) struct NameLimits {
+    names: HashMap<String, NameCount>,
+}
+
+struct NameCount {
+    limit: usize,
+    open: AtomicUsize,
+    armed: AtomicBool,
+}
+
+impl NameLimits {
+    pub(crate) fn new(limits: &HashMap<String, usize>) -> Self {
+        NameLimits {
+            names: limits
+                .iter()
+                .map(|(name, &limit)| {
+                    let count = NameCount {
+                        limit,
+                        open: AtomicUsize::new(0),
+                        armed: AtomicBool::new(true),
+                    };
+                    (name.clone(), count)
+                })
+                .collect(),
+        }
+    }
+
+    /// Counts a tracked span named `name`, returning the new open count the
+    /// first time it rises above the name's limit. Like
+    /// [`Builder::on_threshold_exceeded`](crate::Builder::on_threshold_exceeded)
+    /// this re-arms once the count drops back below the limit.
+    pub(crate) fn opened(&self, name: &str) -> Option<usize> {
+        let count = self.names.get(name)?;
+        let open = count.open.fetch_add(1, Ordering::AcqRel) + 1;
+        (open > count.limit && count.armed.swap(false, Ordering::AcqRel)).then_some(open)
+    }
+
+    pub(crate) fn closed(&self, name: &str) {
+        let Some(count) = self.names.get(name) else {
+            return;
+        };
+        let open = count.open.fetch_sub(1, Ordering::AcqRel) - 1;
+        if open < count.limit {
+            count.armed.store(true, Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Export to Perfetto's protobuf trace format, see
+//! [`SpanSnapshot::to_perfetto_trace`].
+//!
+//! Only the handful of `perfetto.protos` messages and fields needed for
+//! track events are declared here, with their upstream field numbers.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+
+use crate::{SpanRecord, SpanSnapshot};
+
+/// Every packet is written as if from one producer sequence.
+const SEQUENCE_ID: u32 = 1;
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Trace {
+    #[prost(message, repeated, tag = "1")]
+    pub(crate) packet: Vec<TracePacket>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct TracePacket {
+    #[prost(uint64, optional, tag = "8")]
+    pub(crate) timestamp: Option<u64>,
+    #[prost(uint32, optional, tag = "10")]
+    pub(crate) trusted_packet_sequence_id: Option<u32>,
+    #[prost(message, optional, tag = "11")]
+    pub(crate) track_event: Option<TrackEvent>,
+    #[prost(message, optional, tag = "60")]
+    pub(crate) track_descriptor: Option<TrackDescriptor>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct TrackEvent {
+    #[prost(enumeration = "EventType", optional, tag = "9")]
+    pub(crate) r#type: Option<i32>,
+    #[prost(uint64, optional, tag = "11")]
+    pub(crate) track_uuid: Option<u64>,
+    #[prost(string, repeated, tag = "22")]
+    pub(crate) categories: Vec<String>,
+    #[prost(string, optional, tag = "23")]
+    pub(crate) name: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct TrackDescriptor {
+    #[prost(uint64, optional, tag = "1")]
+    pub(crate) uuid: Option<u64>,
+    #[prost(string, optional, tag = "2")]
+    pub(crate) name: Option<String>,
+    #[prost(uint64, optional, tag = "5")]
+    pub(crate) parent_uuid: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+#[repr(i32)]
+pub(crate) enum EventType {
+    Unspecified = 0,
+    SliceBegin = 1,
+    SliceEnd = 2,
+}
+
+impl SpanSnapshot {
+    /// Encodes the open spans as a Perfetto protobuf trace, which can be
+    /// opened in the Perfetto UI.
+    ///
+    /// Each span gets its own track, nested under its parent's track, with
+    /// a slice that begins at the span's wall-clock open time. The spans are
+    /// still open, so the slices are left unfinished and show as running to
+    /// the end of the trace.
+    pub fn to_perfetto_trace(&self) -> Vec<u8> {
+        let mut packet = Vec::new();
+        for span in self.open_spans_by_creation() {
+            let uuid = track_uuid(span);
+            packet.push(TracePacket {
+                trusted_packet_sequence_id: Some(SEQUENCE_ID),
+                track_descriptor: Some(TrackDescriptor {
+                    uuid: Some(uuid),
+                    name: Some(span.meta.name.to_string()),
+                    parent_uuid: span
+                        .parent
+                        .as_ref()
+                        .and_then(|parent| self.get(parent))
+                        .map(track_uuid),
+                }),
+                ..Default::default()
+            });
+            packet.push(TracePacket {
+                timestamp: Some(unix_nanos(span.created_at)),
+                trusted_packet_sequence_id: Some(SEQUENCE_ID),
+                track_event: Some(TrackEvent {
+                    r#type: Some(EventType::SliceBegin as i32),
+                    track_uuid: Some(uuid),
+                    categories: vec![span.meta.target.to_string()],
+                    name: Some(span.meta.name.to_string()),
+                }),
+                ..Default::default()
+            });
+        }
+        Trace { packet }.encode_to_vec()
+    }
+}
+
+/// Span ids can be reused, so tracks are keyed by creation order instead.
+/// Zero is avoided as Perfetto treats it as no track.
+fn track_uuid(span: &SpanRecord) -> u64 {
+    span.seq + 1
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_perfetto_trace() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let parent = info_span!(target: "app", "parent");
+        let _child = info_span!(parent: &parent, "child");
+
+        let trace = Trace::decode(span_dumper.snapshot().to_perfetto_trace().as_slice()).unwrap();
+        let tracks: Vec<_> = trace
+            .packet
+            .iter()
+            .filter_map(|packet| packet.track_descriptor.as_ref())
+            .map(|track| {
+                (
+                    track.name.as_deref().unwrap(),
+                    track.uuid,
+                    track.parent_uuid,
+                )
+            })
+            .collect();
+        assert_eq!(
+            tracks,
+            [("parent", Some(1), None), ("child", Some(2), Some(1))]
+        );
+
+        let begin = trace.packet[1].track_event.as_ref().unwrap();
+        assert_eq!(begin.r#type, Some(EventType::SliceBegin as i32));
+        assert_eq!(begin.track_uuid, Some(1));
+        assert_eq!(begin.categories, ["app"]);
+        assert!(trace.packet[1].timestamp.unwrap() > 0);
+        assert!(trace
+            .packet
+            .iter()
+            .all(|packet| packet.trusted_packet_sequence_id == Some(SEQUENCE_ID)));
+    }
+}
@@ -0,0 +1,1034 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::field::{Field, Visit};
+use tracing::span;
+
+use crate::builder::Clock;
+
+/// The descriptive parts of a span's metadata, copied out at creation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanMeta {
+    pub name: &'static str,
+    pub target: &'static str,
+    pub level: tracing::Level,
+    /// The Rust module the span was created in, which can be more precise
+    /// than `target`. `None` for spans whose callsite does not record one.
+    pub module_path: Option<&'static str>,
+}
+
+impl From<&'static tracing::Metadata<'static>> for SpanMeta {
+    fn from(metadata: &'static tracing::Metadata<'static>) -> Self {
+        SpanMeta {
+            name: metadata.name(),
+            target: metadata.target(),
+            level: *metadata.level(),
+            module_path: metadata.module_path(),
+        }
+    }
+}
+
+/// How a span's parent was determined when it was created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParentKind {
+    /// The parent was passed explicitly, e.g. `info_span!(parent: &p, ..)`.
+    Explicit(span::Id),
+    /// The span was created inside the contextual current span.
+    Contextual(span::Id),
+    /// The span was explicitly made a root, or was created with no current
+    /// span.
+    Root,
+}
+
+impl ParentKind {
+    pub fn id(&self) -> Option<&span::Id> {
+        match self {
+            ParentKind::Explicit(id) | ParentKind::Contextual(id) => Some(id),
+            ParentKind::Root => None,
+        }
+    }
+}
+
+/// Which span is stored as a record's [`SpanRecord::parent`], set with
+/// [`Builder::parent_strategy`](crate::Builder::parent_strategy). The
+/// [`SpanRecord::parent_kind`] is recorded the same way under every strategy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParentStrategy {
+    /// Only parents passed explicitly, e.g. `info_span!(parent: &p, ..)`, so
+    /// spans created inside another span are roots in trees.
+    ExplicitOnly,
+    /// The explicit parent if there is one, otherwise the span that was
+    /// current when the span was created.
+    #[default]
+    ContextualPreferred,
+    /// Like [`ParentStrategy::ContextualPreferred`], and also stores the
+    /// span that was current at creation in
+    /// [`SpanRecord::contextual_parent`], even for spans with an explicit
+    /// parent.
+    Both,
+}
+
+impl ParentStrategy {
+    pub(crate) fn parent(self, kind: &ParentKind) -> Option<span::Id> {
+        match (self, kind) {
+            (ParentStrategy::ExplicitOnly, ParentKind::Explicit(id)) => Some(id.clone()),
+            (ParentStrategy::ExplicitOnly, _) => None,
+            (_, kind) => kind.id().cloned(),
+        }
+    }
+}
+
+/// How a span fared against [`Builder::capture_if`](crate::Builder::capture_if)
+/// and [`Builder::require_field`](crate::Builder::require_field), stored in
+/// [`SpanRecord::filter`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// No filter is configured.
+    #[default]
+    Unfiltered,
+    /// The span passed every configured filter.
+    Passed,
+    /// A filter rejected the span, which is only kept with
+    /// [`Builder::keep_rejected`](crate::Builder::keep_rejected).
+    Rejected,
+}
+
+/// A tracked span.
+///
+/// Two records are equal when their `id`, `parent` and `meta` are equal. The
+/// raw `metadata` pointer, `seq` and the open timestamps do not participate.
+/// Records hash by `id` alone, which agrees with equality, so they can be
+/// kept in a `HashSet` or used as map keys standing for the span.
+#[derive(Debug, Clone)]
+pub struct SpanRecord {
+    pub id: span::Id,
+    /// The parent used to build trees, chosen by
+    /// [`Builder::parent_strategy`](crate::Builder::parent_strategy).
+    pub parent: Option<span::Id>,
+    pub parent_kind: ParentKind,
+    /// The span that was current when this one was created, only recorded
+    /// with [`ParentStrategy::Both`].
+    pub contextual_parent: Option<span::Id>,
+    /// `tracing` only hands layers `'static` metadata, so callsites created
+    /// at runtime have to be leaked before they can be registered and this
+    /// reference cannot outlive them. The commonly used parts are also
+    /// copied into `meta`, which should be preferred.
+    pub metadata: &'static tracing::Metadata<'static>,
+    pub meta: SpanMeta,
+    /// Position of the span in creation order, assigned by the layer.
+    pub seq: u64,
+    /// Monotonic time the span was opened, used for ages and durations.
+    pub opened_at: Instant,
+    /// When the span closed. Only set on the closed spans that
+    /// [`SpanDumpLayer::snapshot_with_closed`](crate::SpanDumpLayer::snapshot_with_closed)
+    /// includes, `None` for open spans.
+    pub closed_at: Option<Instant>,
+    /// Whether the span has ever been entered. Unlike
+    /// [`SpanTiming::is_entered`] this stays set after the span exits, and
+    /// it is tracked without [`Builder::track_enters`](crate::Builder::track_enters).
+    pub was_entered: bool,
+    pub(crate) events: u64,
+    /// The children opened under this span so far, numbering the next one's
+    /// [`SpanRecord::sibling_index`].
+    pub(crate) children_opened: u32,
+    /// Whether the span passed the layer's filters.
+    pub filter: FilterDecision,
+    /// The clock the timestamps above were taken with.
+    pub(crate) clock: RecordClock,
+    /// Wall-clock time the span was opened, comparable across processes.
+    pub created_at: SystemTime,
+    /// Field values, only populated when field capture is enabled.
+    pub fields: SpanFields,
+    /// Ancestor ids at creation, nearest first, as resolved by the
+    /// subscriber's registry. Only populated when ancestry capture is
+    /// enabled.
+    pub ancestry: Vec<span::Id>,
+    /// The [`SpanDumpLayer::dispatch_id`](crate::SpanDumpLayer::dispatch_id)
+    /// of the layer that recorded the span.
+    pub dispatch_id: Option<u64>,
+    /// Time spent entered, only tracked when enabled with
+    /// [`Builder::track_enters`](crate::Builder::track_enters).
+    pub timing: SpanTiming,
+    /// The tokio task the span was created in, `None` outside of a task.
+    #[cfg(feature = "tokio-task-id")]
+    pub task_id: Option<tokio::task::Id>,
+    /// The backtrace of the span's creation, when enabled with
+    /// [`Builder::capture_backtrace`](crate::Builder::capture_backtrace).
+    #[cfg(feature = "backtrace")]
+    pub backtrace: Option<Arc<std::backtrace::Backtrace>>,
+    /// How many children its parent had opened before this span, i.e. its
+    /// position among all of them, open or closed. Only recorded with
+    /// [`Builder::sibling_index`](crate::Builder::sibling_index), and `None`
+    /// for spans whose parent is not tracked.
+    pub sibling_index: Option<u32>,
+    /// The class assigned by
+    /// [`Builder::with_name_classifier`](crate::Builder::with_name_classifier).
+    pub class: Option<u32>,
+    /// Custom data attached with
+    /// [`SpanDumpLayer::with_record_mut`](crate::SpanDumpLayer::with_record_mut).
+    pub extensions: SpanExtensions,
+}
+
+impl PartialEq for SpanRecord {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.parent == other.parent && self.meta == other.meta
+    }
+}
+
+impl Eq for SpanRecord {}
+
+impl Hash for SpanRecord {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl SpanRecord {
+    /// The span's verbosity, the same as `meta.level`, for comparisons such
+    /// as `record.level() <= Level::WARN`.
+    pub fn level(&self) -> tracing::Level {
+        self.meta.level
+    }
+
+    /// How long the span has been open, measured with the layer's clock,
+    /// see [`Builder::with_clock`](crate::Builder::with_clock).
+    pub fn age(&self) -> Duration {
+        self.age_at(self.now())
+    }
+
+    /// How long the span had been open at `now`, or zero if `now` is
+    /// earlier than its open time. For a closed span this stops at
+    /// `closed_at`.
+    pub fn age_at(&self, now: Instant) -> Duration {
+        let end = self.closed_at.map_or(now, |closed_at| closed_at.min(now));
+        end.saturating_duration_since(self.opened_at)
+    }
+
+    /// How many events were emitted in the span, not counting events in its
+    /// children. Always zero unless enabled with
+    /// [`Builder::count_events`](crate::Builder::count_events).
+    pub fn event_count(&self) -> u64 {
+        self.events
+    }
+
+    /// Total time the span has spent entered, including a current entry
+    /// measured with the layer's clock.
+    pub fn busy_duration(&self) -> Duration {
+        self.busy_duration_at(self.now())
+    }
+
+    /// Like [`SpanRecord::busy_duration`], measuring a current entry up to
+    /// `now`.
+    pub fn busy_duration_at(&self, now: Instant) -> Duration {
+        self.timing.busy_at(now)
+    }
+
+    /// Time the span has been open but not entered, e.g. waiting on I/O.
+    /// This is the whole age when enters are not tracked. Measured with the
+    /// layer's clock.
+    pub fn idle_duration(&self) -> Duration {
+        self.idle_duration_at(self.now())
+    }
+
+    /// Like [`SpanRecord::idle_duration`], measured at `now`.
+    pub fn idle_duration_at(&self, now: Instant) -> Duration {
+        self.age_at(now).saturating_sub(self.busy_duration_at(now))
+    }
+
+    /// The current time by the clock of the layer that recorded the span.
+    pub(crate) fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// The captured value of the field `name` with its recorded type, see
+    /// [`SpanFields::get_typed`].
+    pub fn field_typed(&self, name: &str) -> Option<FieldValue<'_>> {
+        self.fields.get_typed(name)
+    }
+
+    /// The captured value of the field `name`, rendered as a string.
+    pub fn field_str(&self, name: &str) -> Option<&str> {
+        self.fields.get(name)
+    }
+
+    /// The captured fields as `{key=value, ..}` in recording order, the way
+    /// log lines show span fields. `{}` if no fields were captured.
+    pub fn format_fields(&self) -> String {
+        let mut out = String::from("{");
+        for (i, (name, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            out.push_str(name);
+            out.push('=');
+            out.push_str(value);
+        }
+        out.push('}');
+        out
+    }
+}
+
+/// The [`Builder::with_clock`](crate::Builder::with_clock) clock kept with
+/// each record, so ages measured without an explicit time use the same clock
+/// as the record's timestamps. `None` for the real clock.
+#[derive(Clone, Default)]
+pub(crate) struct RecordClock(pub(crate) Option<Arc<Clock>>);
+
+impl RecordClock {
+    pub(crate) fn now(&self) -> Instant {
+        self.0.as_ref().map_or_else(Instant::now, |clock| clock())
+    }
+}
+
+impl fmt::Debug for RecordClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "custom" } else { "real" })
+    }
+}
+
+/// How long a span has spent entered.
+///
+/// A span entered on several threads at once, or re-entered while already
+/// entered, counts as busy from the first enter until the last exit.
+#[derive(Debug, Clone, Default)]
+pub struct SpanTiming {
+    /// Busy time from completed entries.
+    pub busy: Duration,
+    /// How many times the span has been entered.
+    pub enter_count: u64,
+    /// The deepest the span's scope, the span itself and its ancestors in
+    /// the subscriber's registry, was at any of its enters. A cheap measure
+    /// of how deep execution was while the span was active.
+    pub max_enter_depth: usize,
+    entered_at: Option<Instant>,
+    depth: usize,
+}
+
+impl SpanTiming {
+    /// Whether the span is currently entered.
+    pub fn is_entered(&self) -> bool {
+        self.depth > 0
+    }
+
+    fn busy_at(&self, now: Instant) -> Duration {
+        let current = self
+            .entered_at
+            .map(|entered_at| now.saturating_duration_since(entered_at))
+            .unwrap_or_default();
+        self.busy + current
+    }
+
+    pub(crate) fn enter(&mut self, now: Instant, depth: usize) {
+        self.enter_count += 1;
+        self.max_enter_depth = self.max_enter_depth.max(depth);
+        if self.depth == 0 {
+            self.entered_at = Some(now);
+        }
+        self.depth += 1;
+    }
+
+    pub(crate) fn exit(&mut self, now: Instant) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {
+            if let Some(entered_at) = self.entered_at.take() {
+                self.busy += now.saturating_duration_since(entered_at);
+            }
+        }
+    }
+}
+
+/// Typed values attached to a [`SpanRecord`], at most one per type.
+///
+/// Values are shared between a record and its copies in snapshots, so they
+/// cannot be mutated in place; insert a new value to replace one. Nothing is
+/// allocated until the first insert.
+#[derive(Clone, Default)]
+pub struct SpanExtensions {
+    map: Option<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl SpanExtensions {
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.map
+            .as_ref()?
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Attaches `value`, returning the value of the same type it replaces.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<Arc<T>> {
+        self.map
+            .get_or_insert_with(Default::default)
+            .insert(TypeId::of::<T>(), Arc::new(value))
+            .and_then(|old| old.downcast().ok())
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<Arc<T>> {
+        self.map
+            .as_mut()?
+            .remove(&TypeId::of::<T>())
+            .and_then(|old| old.downcast().ok())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.as_ref().map_or(0, |map| map.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for SpanExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpanExtensions")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Field values recorded on a span, in the order they were first recorded.
+///
+/// Every value is kept rendered as a string, and values recorded as one of
+/// `tracing`'s primitive types also keep their type, see
+/// [`SpanFields::get_typed`].
+///
+/// Storage is allocated on the first recorded value, so spans without
+/// fields cost no allocation even with field capture enabled; the
+/// `span_creation` bench guards this.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpanFields {
+    values: Vec<(&'static str, String, FieldKind)>,
+}
+
+/// A captured field value with the type it was recorded as; see
+/// [`SpanFields::get_typed`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldValue<'a> {
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Bool(bool),
+    Str(&'a str),
+    /// A value recorded through its `Debug` implementation, as rendered.
+    Debug(&'a str),
+}
+
+/// The typed part of a captured value. Floats are kept as their bits so
+/// fields stay `Eq`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldKind {
+    I64(i64),
+    U64(u64),
+    F64(u64),
+    Bool(bool),
+    Str,
+    Debug,
+}
+
+impl SpanFields {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.find(name).map(|(_, value, _)| value.as_str())
+    }
+
+    /// The value of `name` with the type it was recorded as. Values that are
+    /// not `i64`, `u64`, `f64`, `bool` or `str` are [`FieldValue::Debug`].
+    pub fn get_typed(&self, name: &str) -> Option<FieldValue<'_>> {
+        self.find(name).map(|(_, value, kind)| match *kind {
+            FieldKind::I64(v) => FieldValue::I64(v),
+            FieldKind::U64(v) => FieldValue::U64(v),
+            FieldKind::F64(bits) => FieldValue::F64(f64::from_bits(bits)),
+            FieldKind::Bool(v) => FieldValue::Bool(v),
+            FieldKind::Str => FieldValue::Str(value),
+            FieldKind::Debug => FieldValue::Debug(value),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        self.values
+            .iter()
+            .map(|(field, value, _)| (*field, value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    fn find(&self, name: &str) -> Option<&(&'static str, String, FieldKind)> {
+        self.values.iter().find(|(field, _, _)| *field == name)
+    }
+
+    fn set(&mut self, name: &'static str, value: String, kind: FieldKind) {
+        match self.values.iter_mut().find(|(field, _, _)| *field == name) {
+            Some(existing) => *existing = (name, value, kind),
+            None => self.values.push((name, value, kind)),
+        }
+    }
+
+    /// A visitor recording into these fields according to `rules`.
+    pub(crate) fn capture<'a>(&'a mut self, rules: &'a FieldRules) -> FieldCapture<'a> {
+        FieldCapture {
+            fields: self,
+            rules,
+            max_fields: rules.max_fields,
+        }
+    }
+
+    /// Like [`SpanFields::capture`] without applying
+    /// [`FieldRules::max_fields`], for values later added to a record with
+    /// [`SpanFields::merge_capped`].
+    pub(crate) fn capture_uncapped<'a>(&'a mut self, rules: &'a FieldRules) -> FieldCapture<'a> {
+        FieldCapture {
+            fields: self,
+            rules,
+            max_fields: None,
+        }
+    }
+
+    /// Adds `other`'s values, overwriting any with the same name.
+    pub(crate) fn merge(&mut self, other: SpanFields) {
+        self.merge_capped(other, None);
+    }
+
+    /// Like [`SpanFields::merge`], skipping new fields once there are
+    /// `max_fields`.
+    pub(crate) fn merge_capped(&mut self, other: SpanFields, max_fields: Option<usize>) {
+        for (name, value, kind) in other.values {
+            if self.find(name).is_none() && max_fields.is_some_and(|max| self.len() >= max) {
+                continue;
+            }
+            self.set(name, value, kind);
+        }
+    }
+}
+
+impl Visit for SpanFields {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.capture(&FieldRules::NONE).record_i64(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.capture(&FieldRules::NONE).record_u64(field, value);
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.capture(&FieldRules::NONE).record_f64(field, value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.capture(&FieldRules::NONE).record_bool(field, value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.capture(&FieldRules::NONE).record_str(field, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.capture(&FieldRules::NONE).record_debug(field, value);
+    }
+}
+
+/// Whether `attrs` carries a value for every field named in `names`.
+pub(crate) fn has_fields(attrs: &span::Attributes<'_>, names: &[String]) -> bool {
+    struct Presence<'a> {
+        names: &'a [String],
+        seen: Vec<bool>,
+    }
+
+    impl Visit for Presence<'_> {
+        fn record_debug(&mut self, field: &Field, _: &dyn fmt::Debug) {
+            if let Some(i) = self.names.iter().position(|name| name == field.name()) {
+                self.seen[i] = true;
+            }
+        }
+    }
+
+    if names.is_empty() {
+        return true;
+    }
+    let mut presence = Presence {
+        names,
+        seen: vec![false; names.len()],
+    };
+    attrs.record(&mut presence);
+    presence.seen.into_iter().all(|seen| seen)
+}
+
+/// The value stored for fields listed in
+/// [`Builder::redact_fields`](crate::Builder::redact_fields).
+pub const REDACTED: &str = "***";
+
+/// Appended to values cut short by
+/// [`Builder::max_field_len`](crate::Builder::max_field_len).
+pub const TRUNCATED: &str = "…";
+
+/// How the layer records span fields, set up through the builder.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FieldRules {
+    pub(crate) redact: Vec<String>,
+    pub(crate) max_len: Option<usize>,
+    pub(crate) max_fields: Option<usize>,
+}
+
+impl FieldRules {
+    const NONE: FieldRules = FieldRules {
+        redact: Vec::new(),
+        max_len: None,
+        max_fields: None,
+    };
+}
+
+/// See [`SpanFields::capture`].
+pub(crate) struct FieldCapture<'a> {
+    fields: &'a mut SpanFields,
+    rules: &'a FieldRules,
+    max_fields: Option<usize>,
+}
+
+impl FieldCapture<'_> {
+    /// Renders and stores `value` unless the field is over
+    /// [`FieldRules::max_fields`], storing [`REDACTED`] instead for redacted
+    /// fields. Rendering stops at [`FieldRules::max_len`].
+    fn store(&mut self, field: &Field, kind: FieldKind, value: fmt::Arguments<'_>) {
+        let name = field.name();
+        let is_new = self.fields.find(name).is_none();
+        if is_new && self.max_fields.is_some_and(|max| self.fields.len() >= max) {
+            return;
+        }
+        if self.rules.redact.iter().any(|redacted| redacted == name) {
+            self.fields.set(name, REDACTED.to_string(), FieldKind::Str);
+            return;
+        }
+
+        let mut rendered = Truncating {
+            out: String::new(),
+            limit: self.rules.max_len.unwrap_or(usize::MAX),
+            truncated: false,
+        };
+        let _ = fmt::write(&mut rendered, value);
+        if rendered.truncated {
+            rendered.out.push_str(TRUNCATED);
+        }
+        self.fields.set(name, rendered.out, kind);
+    }
+}
+
+impl Visit for FieldCapture<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.store(field, FieldKind::I64(value), format_args!("{}", value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.store(field, FieldKind::U64(value), format_args!("{}", value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        let kind = FieldKind::F64(value.to_bits());
+        self.store(field, kind, format_args!("{:?}", value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.store(field, FieldKind::Bool(value), format_args!("{}", value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.store(field, FieldKind::Str, format_args!("{}", value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.store(field, FieldKind::Debug, format_args!("{:?}", value));
+    }
+}
+
+/// A writer that keeps at most `limit` bytes, cut at a character boundary,
+/// and then fails so formatting stops early.
+struct Truncating {
+    out: String,
+    limit: usize,
+    truncated: bool,
+}
+
+impl fmt::Write for Truncating {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let remaining = self.limit - self.out.len();
+        if s.len() <= remaining {
+            self.out.push_str(s);
+            return Ok(());
+        }
+
+        let mut end = remaining;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.out.push_str(&s[..end]);
+        self.truncated = true;
+        Err(fmt::Error)
+    }
+}
+
+/// A span that has closed, kept for post-mortem inspection.
+#[derive(Debug, Clone)]
+pub struct SpanLifetime {
+    pub record: SpanRecord,
+    pub closed_at: Instant,
+}
+
+impl SpanLifetime {
+    /// How long the span was open for.
+    pub fn duration(&self) -> Duration {
+        self.closed_at
+            .saturating_duration_since(self.record.opened_at)
+    }
+
+    /// Total time the span spent entered.
+    pub fn busy_duration(&self) -> Duration {
+        self.record.busy_duration_at(self.closed_at)
+    }
+
+    /// Time the span was open but not entered.
+    pub fn idle_duration(&self) -> Duration {
+        self.record.idle_duration_at(self.closed_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::{info_span, Span};
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_typed_fields() {
+        let span_dumper = SpanDumpLayer::builder().capture_fields(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let span = info_span!(
+            "typed",
+            signed = -1,
+            unsigned = 2u64,
+            ratio = 0.5,
+            ok = true,
+            user = "alice",
+            point = ?(1, 2)
+        );
+
+        let snapshot = span_dumper.snapshot();
+        let record = snapshot.get(&span.id().unwrap()).unwrap();
+        assert_eq!(record.field_typed("signed"), Some(FieldValue::I64(-1)));
+        assert_eq!(record.field_typed("unsigned"), Some(FieldValue::U64(2)));
+        assert_eq!(record.field_typed("ratio"), Some(FieldValue::F64(0.5)));
+        assert_eq!(record.field_typed("ok"), Some(FieldValue::Bool(true)));
+        assert_eq!(record.field_typed("user"), Some(FieldValue::Str("alice")));
+        assert_eq!(
+            record.field_typed("point"),
+            Some(FieldValue::Debug("(1, 2)"))
+        );
+        assert_eq!(record.field_str("ratio"), Some("0.5"));
+        assert_eq!(record.field_str("missing"), None);
+    }
+
+    #[test]
+    fn test_record_eq() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let parent = info_span!("parent");
+        let child = tracing::warn_span!(target: "custom", parent: &parent, "child");
+        let id = child.id().unwrap();
+
+        // Only the id, parent and metadata are compared, so the rest is
+        // copied from the stored record, with a few fields changed to show
+        // they are ignored.
+        let snapshot = span_dumper.snapshot();
+        let expected = SpanRecord {
+            id: id.clone(),
+            parent: parent.id(),
+            meta: SpanMeta {
+                name: "child",
+                target: "custom",
+                level: tracing::Level::WARN,
+                module_path: Some(module_path!()),
+            },
+            seq: 99,
+            opened_at: Instant::now(),
+            was_entered: true,
+            ..snapshot.get(&id).unwrap().clone()
+        };
+
+        assert_eq!(snapshot.get(&id), Some(&expected));
+        assert_ne!(snapshot.get(&parent.id().unwrap()), Some(&expected));
+
+        // Records hash by id alone, so the lazily resolved backtrace's
+        // interior mutability cannot change a record's hash.
+        #[cfg_attr(feature = "backtrace", allow(clippy::mutable_key_type))]
+        let set: std::collections::HashSet<_> = snapshot.open_spans().cloned().collect();
+        assert!(set.contains(&expected));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_level() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let warn = tracing::warn_span!("warn");
+        let _info = info_span!("info");
+
+        let snapshot = span_dumper.snapshot();
+        let severe: Vec<_> = snapshot
+            .open_spans()
+            .filter(|span| span.level() <= tracing::Level::WARN)
+            .map(|span| span.id.clone())
+            .collect();
+        assert_eq!(severe, [warn.id().unwrap()]);
+    }
+
+    #[test]
+    fn test_no_fields_no_allocation() {
+        let span_dumper = SpanDumpLayer::builder().capture_fields(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let plain = info_span!("plain");
+        let empty = info_span!("empty", late = tracing::field::Empty);
+        let with_field = info_span!("with_field", n = 1);
+
+        let snapshot = span_dumper.snapshot();
+        let capacity = |span: &Span| {
+            snapshot
+                .get(&span.id().unwrap())
+                .unwrap()
+                .fields
+                .values
+                .capacity()
+        };
+        assert_eq!(capacity(&plain), 0);
+        assert_eq!(capacity(&empty), 0);
+        assert!(capacity(&with_field) > 0);
+    }
+
+    #[test]
+    fn test_max_enter_depth() {
+        let span_dumper = SpanDumpLayer::builder().track_enters(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let outer = info_span!("outer");
+        let inner = outer.in_scope(|| info_span!("inner"));
+        let leaf = inner.in_scope(|| info_span!("leaf"));
+        leaf.in_scope(|| {});
+        let idle = info_span!("idle");
+
+        let snapshot = span_dumper.snapshot();
+        let depth = |span: &Span| {
+            snapshot
+                .get(&span.id().unwrap())
+                .unwrap()
+                .timing
+                .max_enter_depth
+        };
+        assert_eq!([&outer, &inner, &leaf, &idle].map(depth), [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_event_count() {
+        let span_dumper = SpanDumpLayer::builder().count_events(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let chatty = info_span!("chatty");
+        let quiet = info_span!("quiet");
+        chatty.in_scope(|| {
+            tracing::info!("one");
+            tracing::info!("two");
+            let _child = info_span!("child").entered();
+            tracing::info!("in child");
+        });
+        tracing::info!(parent: &chatty, "explicit");
+
+        let snapshot = span_dumper.snapshot();
+        let count = |span: &Span| snapshot.get(&span.id().unwrap()).unwrap().event_count();
+        assert_eq!(count(&chatty), 3);
+        assert_eq!(count(&quiet), 0);
+    }
+
+    #[test]
+    fn test_parent_kind() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let explicit = info_span!(parent: &root, "explicit");
+        let _entered = root.clone().entered();
+        let contextual = info_span!("contextual");
+        let detached = info_span!(parent: Span::none(), "detached");
+
+        let snapshot = span_dumper.snapshot();
+        let kind = |span: &Span| {
+            snapshot
+                .get(&span.id().unwrap())
+                .unwrap()
+                .parent_kind
+                .clone()
+        };
+        let root_id = root.id().unwrap();
+
+        assert_eq!(kind(&root), ParentKind::Root);
+        assert_eq!(kind(&explicit), ParentKind::Explicit(root_id.clone()));
+        assert_eq!(kind(&contextual), ParentKind::Contextual(root_id));
+        assert_eq!(kind(&detached), ParentKind::Root);
+    }
+
+    #[test]
+    fn test_parent_strategy() {
+        let resolve = |strategy: ParentStrategy| {
+            let span_dumper = SpanDumpLayer::builder().parent_strategy(strategy).build();
+
+            let _sub = tracing_subscriber::registry()
+                .with(span_dumper.clone())
+                .set_default();
+
+            let root = info_span!("root");
+            let other = info_span!("other");
+            let _entered = root.clone().entered();
+            let contextual = info_span!("contextual");
+            let explicit = info_span!(parent: &other, "explicit");
+
+            let snapshot = span_dumper.snapshot();
+            let record = |span: &Span| snapshot.get(&span.id().unwrap()).unwrap().clone();
+            let (root, other) = (root.id(), other.id());
+            let parents = [record(&contextual), record(&explicit)]
+                .map(|record| (record.parent, record.contextual_parent));
+            (parents, root, other)
+        };
+
+        let (parents, _, other) = resolve(ParentStrategy::ExplicitOnly);
+        assert_eq!(parents, [(None, None), (other, None)]);
+
+        let (parents, root, other) = resolve(ParentStrategy::default());
+        assert_eq!(parents, [(root, None), (other, None)]);
+
+        let (parents, root, other) = resolve(ParentStrategy::Both);
+        assert_eq!(parents, [(root.clone(), root.clone()), (other, root)]);
+    }
+
+    #[test]
+    fn test_sibling_index() {
+        let span_dumper = SpanDumpLayer::builder().sibling_index(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let first = info_span!(parent: &root, "first");
+        let second = info_span!(parent: &root, "second");
+        drop(first);
+        let third = info_span!(parent: &root, "third");
+        let nested = info_span!(parent: &third, "nested");
+
+        let snapshot = span_dumper.snapshot();
+        let index = |span: &Span| snapshot.get(&span.id().unwrap()).unwrap().sibling_index;
+        assert_eq!(index(&root), None);
+        assert_eq!(index(&second), Some(1));
+        assert_eq!(index(&third), Some(2));
+        assert_eq!(index(&nested), Some(0));
+    }
+
+    #[test]
+    fn test_capture_ancestry() {
+        let span_dumper = SpanDumpLayer::builder().capture_ancestry(true).build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let middle = info_span!(parent: &root, "middle");
+        let leaf = middle.in_scope(|| info_span!("leaf"));
+        let ids = [&root, &middle, &leaf].map(|span| span.id().unwrap());
+
+        let snapshot = span_dumper.snapshot();
+        let leaf = snapshot.get(&ids[2]).unwrap();
+        assert_eq!(leaf.ancestry, [ids[1].clone(), ids[0].clone()]);
+        assert!(snapshot.get(&ids[0]).unwrap().ancestry.is_empty());
+    }
+
+    #[test]
+    fn test_dynamic_callsite() {
+        use std::sync::OnceLock;
+        use tracing::callsite::{Callsite, Identifier};
+        use tracing::field::FieldSet;
+        use tracing::metadata::Kind;
+        use tracing::subscriber::Interest;
+        use tracing::{Level, Metadata};
+
+        struct DynamicCallsite(OnceLock<Metadata<'static>>);
+
+        impl Callsite for DynamicCallsite {
+            fn set_interest(&self, _interest: Interest) {}
+
+            fn metadata(&self) -> &Metadata<'_> {
+                self.0.get().unwrap()
+            }
+        }
+
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let name: &'static str = Box::leak(format!("dynamic_{}", 1).into_boxed_str());
+        let callsite: &'static DynamicCallsite =
+            Box::leak(Box::new(DynamicCallsite(OnceLock::new())));
+        let _ = callsite.0.set(Metadata::new(
+            name,
+            "dynamic",
+            Level::INFO,
+            None,
+            None,
+            None,
+            FieldSet::new(&[], Identifier(callsite)),
+            Kind::SPAN,
+        ));
+        tracing::callsite::register(callsite);
+
+        let metadata = callsite.0.get().unwrap();
+        let span = Span::new(metadata, &metadata.fields().value_set(&[]));
+
+        let snapshot = span_dumper.snapshot();
+        let record = snapshot.get(&span.id().unwrap()).unwrap();
+        assert_eq!(record.meta.name, "dynamic_1");
+        assert_eq!(record.meta.target, "dynamic");
+    }
+}
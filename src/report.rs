@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::{SpanRecord, SpanSnapshot};
+
+/// How many groups [`SpanSnapshot::summary`] lists before eliding the rest.
+const SUMMARY_GROUPS: usize = 5;
+
+impl SpanSnapshot {
+    /// The number of open spans per name, largest first. Ties are ordered
+    /// by name.
+    pub fn report_by_name(&self) -> Vec<(&'static str, usize)> {
+        self.report_by(|span| span.meta.name)
+    }
+
+    /// The number of open spans per target, largest first. Ties are ordered
+    /// by target.
+    pub fn report_by_target(&self) -> Vec<(&'static str, usize)> {
+        self.report_by(|span| span.meta.target)
+    }
+
+    /// A one line overview of the open spans and the most common names,
+    /// e.g. `3 open spans; by name: request=2, db=1`.
+    pub fn summary(&self) -> String {
+        let mut out = format!("{} open spans", self.len());
+        push_groups(&mut out, "by name", &self.report_by_name());
+        out
+    }
+
+    /// Like [`SpanSnapshot::summary`], also listing the most common targets.
+    pub fn summary_with_targets(&self) -> String {
+        let mut out = self.summary();
+        push_groups(&mut out, "by target", &self.report_by_target());
+        out
+    }
+
+    /// A block summarizing the open spans for pasting into an incident
+    /// channel: the count and deepest nesting (roots being depth 0), the
+    /// `top_n` oldest spans, and the `top_n` most common names.
+    pub fn incident_report(&self, top_n: usize) -> String {
+        let mut out = format!("{} open spans", self.len());
+        match self.tree_iter().map(|(depth, _)| depth).max() {
+            Some(depth) => {
+                let _ = writeln!(out, ", max depth {}", depth);
+            }
+            None => {
+                out.push('\n');
+                return out;
+            }
+        }
+
+        let mut oldest: Vec<_> = self.open_spans().collect();
+        oldest.sort_by_key(|span| (std::cmp::Reverse(self.age_of(span)), span.seq));
+        out.push_str("oldest:\n");
+        for span in oldest.into_iter().take(top_n) {
+            let _ = writeln!(
+                out,
+                "  {} ({}) id={} age={:?}",
+                span.meta.name,
+                span.meta.target,
+                span.id.into_u64(),
+                self.age_of(span)
+            );
+        }
+
+        out.push_str("by name:\n");
+        for (name, count) in self.report_by_name().into_iter().take(top_n) {
+            let _ = writeln!(out, "  {}={}", name, count);
+        }
+        out
+    }
+
+    fn report_by(&self, key: impl Fn(&SpanRecord) -> &'static str) -> Vec<(&'static str, usize)> {
+        let mut counts: HashMap<&'static str, usize> = HashMap::new();
+        for span in self.open_spans() {
+            *counts.entry(key(span)).or_default() += 1;
+        }
+
+        let mut report: Vec<_> = counts.into_iter().collect();
+        report.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        report
+    }
+}
+
+fn push_groups(out: &mut String, label: &str, groups: &[(&str, usize)]) {
+    if groups.is_empty() {
+        return;
+    }
+
+    let _ = write!(out, "; {}: ", label);
+    for (i, (key, count)) in groups.iter().take(SUMMARY_GROUPS).enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(out, "{}={}", key, count);
+    }
+    if groups.len() > SUMMARY_GROUPS {
+        let _ = write!(out, ", +{} more", groups.len() - SUMMARY_GROUPS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_reports() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _a = info_span!(target: "db", "query").entered();
+        let _b = info_span!(target: "http", "request").entered();
+        let _c = info_span!(target: "http", "request").entered();
+        let _d = info_span!(target: "cache", "lookup").entered();
+
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(
+            snapshot.report_by_target(),
+            [("http", 2), ("cache", 1), ("db", 1)]
+        );
+        assert_eq!(
+            snapshot.report_by_name(),
+            [("request", 2), ("lookup", 1), ("query", 1)]
+        );
+        assert_eq!(
+            snapshot.summary_with_targets(),
+            "4 open spans; by name: request=2, lookup=1, query=1; \
+             by target: http=2, cache=1, db=1"
+        );
+    }
+
+    #[test]
+    fn test_incident_report() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        assert_eq!(span_dumper.snapshot().incident_report(2), "0 open spans\n");
+
+        let request = info_span!(target: "http", "request");
+        let _query = info_span!(target: "db", parent: &request, "query");
+        let _other = info_span!(target: "http", "request");
+
+        let report = span_dumper.snapshot().incident_report(1);
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines[0], "3 open spans, max depth 1");
+        assert_eq!(lines[1], "oldest:");
+        assert!(lines[2].starts_with(&format!(
+            "  request (http) id={} age=",
+            request.id().unwrap().into_u64()
+        )));
+        assert_eq!(lines[3..], ["by name:", "  request=2"]);
+    }
+}
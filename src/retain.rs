@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use tracing::span;
+
+use crate::SpanLifetime;
+
+/// Closed spans kept until their parent closes, see
+/// [`Builder::retain_closed_children`](crate::Builder::retain_closed_children).
+/// Keyed by the parent's id, which is either open or itself retained here.
+#[derive(Default)]
+pub(crate) struct RetainedChildren {
+    by_parent: HashMap<span::Id, Vec<SpanLifetime>>,
+    len: usize,
+}
+
+impl RetainedChildren {
+    /// Keeps `lifetime` under its parent, returning `false` without keeping
+    /// it if `max` spans are already retained or it has no parent.
+    pub(crate) fn retain(&mut self, lifetime: SpanLifetime, max: usize) -> bool {
+        let Some(parent) = lifetime.record.parent.clone() else {
+            return false;
+        };
+        if self.len >= max {
+            return false;
+        }
+        self.by_parent.entry(parent).or_default().push(lifetime);
+        self.len += 1;
+        true
+    }
+
+    /// Drops the spans retained under `id`, and theirs in turn.
+    pub(crate) fn release(&mut self, id: &span::Id) {
+        let mut pending = vec![id.clone()];
+        while let Some(id) = pending.pop() {
+            for child in self.by_parent.remove(&id).into_iter().flatten() {
+                self.len -= 1;
+                pending.push(child.record.id);
+            }
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &SpanLifetime> {
+        self.by_parent.values().flatten()
+    }
+}
@@ -0,0 +1,893 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::span;
+
+use crate::id::SpanKey;
+use crate::record::FieldRules;
+use crate::{SpanFields, SpanRecord};
+
+#[derive(Clone, Default)]
+pub struct SpanSnapshot {
+    spans: HashMap<span::Id, SpanRecord>,
+    next_seq: u64,
+    taken_at: Option<Instant>,
+    captured_at: Option<SystemTime>,
+}
+
+/// Shows the span count and the most common names rather than every
+/// record, so printing a struct holding a snapshot stays short.
+impl fmt::Debug for SpanSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NAMES: usize = 5;
+
+        let by_name = self.report_by_name();
+        let mut debug = f.debug_struct("SpanSnapshot");
+        debug
+            .field("len", &self.len())
+            .field("by_name", &DebugMap(&by_name[..by_name.len().min(NAMES)]));
+        if by_name.len() > NAMES {
+            debug.finish_non_exhaustive()
+        } else {
+            debug.finish()
+        }
+    }
+}
+
+struct DebugMap<'a>(&'a [(&'static str, usize)]);
+
+impl fmt::Debug for DebugMap<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(name, count)| (name, count)))
+            .finish()
+    }
+}
+
+impl SpanSnapshot {
+    pub fn open_spans(&self) -> impl Iterator<Item = &SpanRecord> {
+        self.spans.values()
+    }
+
+    pub fn get(&self, id: &impl SpanKey) -> Option<&SpanRecord> {
+        self.spans.get(&id.local_id()?)
+    }
+
+    /// Whether any open span matches `pred`, stopping at the first match,
+    /// e.g. `snapshot.any(|span| span.meta.target == "db")`.
+    pub fn any(&self, pred: impl Fn(&SpanRecord) -> bool) -> bool {
+        self.open_spans().any(pred)
+    }
+
+    /// Some open span matching `pred`, stopping at the first match. Spans are
+    /// visited in no particular order, so with several matches any one may
+    /// be returned.
+    pub fn find(&self, pred: impl Fn(&SpanRecord) -> bool) -> Option<&SpanRecord> {
+        self.open_spans().find(|span| pred(span))
+    }
+
+    pub(crate) fn get_mut(&mut self, id: &span::Id) -> Option<&mut SpanRecord> {
+        self.spans.get_mut(id)
+    }
+
+    /// Removes every span, keeping the allocated capacity so the snapshot
+    /// can be refilled with [`SpanDumpLayer::snapshot_into`](crate::SpanDumpLayer::snapshot_into).
+    pub fn clear(&mut self) {
+        self.spans.clear();
+        self.taken_at = None;
+        self.captured_at = None;
+    }
+
+    /// Moves the spans out into a new snapshot, leaving this one empty but
+    /// still numbering new spans after the moved ones.
+    pub(crate) fn take(&mut self) -> SpanSnapshot {
+        SpanSnapshot {
+            spans: std::mem::take(&mut self.spans),
+            next_seq: self.next_seq,
+            taken_at: None,
+            captured_at: None,
+        }
+    }
+
+    /// Replaces this snapshot's contents with a copy of `source`, reusing
+    /// the span map's allocation.
+    pub(crate) fn refill_from(&mut self, source: &SpanSnapshot) {
+        self.clear();
+        self.spans.extend(
+            source
+                .spans
+                .iter()
+                .map(|(id, span)| (id.clone(), span.clone())),
+        );
+        self.next_seq = source.next_seq;
+        self.taken_at = source.taken_at;
+        self.captured_at = source.captured_at;
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// The distinct targets of the open spans, sorted.
+    pub fn targets(&self) -> BTreeSet<&'static str> {
+        self.open_spans().map(|span| span.meta.target).collect()
+    }
+
+    /// The distinct names of the open spans, sorted.
+    pub fn names(&self) -> BTreeSet<&'static str> {
+        self.open_spans().map(|span| span.meta.name).collect()
+    }
+
+    /// When the snapshot was taken, according to the layer's clock. `None`
+    /// for snapshots not taken from a layer.
+    pub fn taken_at(&self) -> Option<Instant> {
+        self.taken_at
+    }
+
+    /// [`SpanSnapshot::taken_at`], or the current time by the clock the spans
+    /// were recorded with for snapshots not taken from a layer.
+    pub(crate) fn now(&self) -> Instant {
+        self.taken_at.unwrap_or_else(|| {
+            self.spans
+                .values()
+                .next()
+                .map_or_else(Instant::now, SpanRecord::now)
+        })
+    }
+
+    /// The wall-clock time the snapshot was taken, `None` for snapshots not
+    /// taken from a layer.
+    pub fn captured_at(&self) -> Option<SystemTime> {
+        self.captured_at
+    }
+
+    /// The age of `span` when the snapshot was taken, or its current age if
+    /// the snapshot has no [`SpanSnapshot::taken_at`].
+    pub fn age_of(&self, span: &SpanRecord) -> Duration {
+        span.age_at(self.taken_at.unwrap_or_else(|| span.now()))
+    }
+
+    /// Open spans in the order they were created.
+    ///
+    /// Span ids may be reused by the subscriber, so this orders by the
+    /// record's `seq` rather than by id.
+    pub fn open_spans_by_creation(&self) -> Vec<&SpanRecord> {
+        let mut spans: Vec<_> = self.open_spans().collect();
+        spans.sort_by_key(|span| span.seq);
+        spans
+    }
+
+    /// The spans every tree view starts from, in creation order.
+    ///
+    /// **Orphans count as roots:** besides spans with no parent, this includes
+    /// spans whose parent is not open in this snapshot, for example because
+    /// it closed while a child was kept alive or was never captured. The tree
+    /// dumps and [`SpanSnapshot::tree_iter`] start from these spans, so every
+    /// open span appears in them exactly once.
+    pub fn roots(&self) -> Vec<&SpanRecord> {
+        self.open_spans_by_creation()
+            .into_iter()
+            .filter(|span| {
+                span.parent
+                    .as_ref()
+                    .is_none_or(|parent| !self.spans.contains_key(parent))
+            })
+            .collect()
+    }
+
+    /// Open spans that have never been entered, in creation order. Often
+    /// futures that were instrumented but dropped or never awaited, see
+    /// [`SpanRecord::was_entered`].
+    pub fn never_entered_spans(&self) -> Vec<&SpanRecord> {
+        self.open_spans_by_creation()
+            .into_iter()
+            .filter(|span| !span.was_entered)
+            .collect()
+    }
+
+    /// Open spans keyed by their parent's id, each list in creation order.
+    /// Spans whose parent is missing from the snapshot are keyed under `None`
+    /// along with the real roots.
+    pub(crate) fn children_index(&self) -> HashMap<Option<&span::Id>, Vec<&SpanRecord>> {
+        let mut index: HashMap<_, Vec<_>> = HashMap::new();
+        for span in self.open_spans_by_creation() {
+            let parent = span
+                .parent
+                .as_ref()
+                .filter(|id| self.spans.contains_key(id));
+            index.entry(parent).or_default().push(span);
+        }
+        index
+    }
+
+    /// The number of open spans whose parent is `id`, following the same
+    /// parent links as the tree dumps. Counts without allocating, but scans
+    /// every open span, so calls for many ids are better served by one pass
+    /// over [`SpanSnapshot::tree_iter`].
+    pub fn child_count(&self, id: &impl SpanKey) -> usize {
+        let Some(id) = id.local_id() else {
+            return 0;
+        };
+        self.open_spans()
+            .filter(|span| span.parent.as_ref() == Some(&id))
+            .count()
+    }
+
+    /// The open ancestors of `id`, nearest first, following the same parent
+    /// links as the tree dumps. The walk stops at the first parent that is
+    /// not open, and at a parent already visited should the links form a
+    /// cycle.
+    pub fn ancestors(&self, id: &impl SpanKey) -> Vec<&SpanRecord> {
+        let Some(id) = id.local_id() else {
+            return Vec::new();
+        };
+        let mut visited = HashSet::from([&id]);
+        let mut ancestors = Vec::new();
+        let mut current = self.spans.get(&id);
+        while let Some(parent) = current
+            .and_then(|span| span.parent.as_ref())
+            .filter(|parent| visited.insert(*parent))
+            .and_then(|parent| self.get(parent))
+        {
+            ancestors.push(parent);
+            current = Some(parent);
+        }
+        ancestors
+    }
+
+    /// The names of `id` and its open [`ancestors`](SpanSnapshot::ancestors),
+    /// root first, joined with dots, e.g. `request.db.query`. `None` if `id`
+    /// is not open.
+    pub fn path_of(&self, id: &impl SpanKey) -> Option<String> {
+        let span = self.get(id)?;
+        let mut names: Vec<_> = self
+            .ancestors(id)
+            .iter()
+            .map(|span| span.meta.name)
+            .collect();
+        names.reverse();
+        names.push(span.meta.name);
+        Some(names.join("."))
+    }
+
+    /// The nearest open span that both `a` and `b` descend from, where a
+    /// span counts as descending from itself. `None` if either is not open
+    /// or they are in different trees.
+    pub fn lowest_common_ancestor(
+        &self,
+        a: &impl SpanKey,
+        b: &impl SpanKey,
+    ) -> Option<&SpanRecord> {
+        let (a, b) = (a.local_id()?, b.local_id()?);
+        let chain = |id: &span::Id| {
+            self.get(id)
+                .into_iter()
+                .chain(self.ancestors(id))
+                .collect::<Vec<_>>()
+        };
+        let b_chain: HashSet<_> = chain(&b).into_iter().map(|span| &span.id).collect();
+        chain(&a)
+            .into_iter()
+            .find(|span| b_chain.contains(&span.id))
+    }
+
+    /// Open spans grouped by their
+    /// [`Builder::with_name_classifier`](crate::Builder::with_name_classifier)
+    /// class, each group in creation order. Unclassified spans are keyed by
+    /// `None`.
+    pub fn group_by_class(&self) -> HashMap<Option<u32>, Vec<&SpanRecord>> {
+        let mut groups: HashMap<_, Vec<_>> = HashMap::new();
+        for span in self.open_spans_by_creation() {
+            groups.entry(span.class).or_default().push(span);
+        }
+        groups
+    }
+
+    /// Open spans grouped by the tokio task they were created in, each group
+    /// in creation order. Spans created outside of a task are keyed by
+    /// `None`.
+    #[cfg(feature = "tokio-task-id")]
+    pub fn group_by_task(&self) -> HashMap<Option<tokio::task::Id>, Vec<&SpanRecord>> {
+        let mut groups: HashMap<_, Vec<_>> = HashMap::new();
+        for span in self.open_spans_by_creation() {
+            groups.entry(span.task_id).or_default().push(span);
+        }
+        groups
+    }
+
+    /// Lazily walks the open spans depth-first from the roots, yielding each
+    /// span with its depth (0 for roots). Children are visited in creation
+    /// order, and each span is yielded at most once.
+    pub fn tree_iter(&self) -> impl Iterator<Item = (usize, &SpanRecord)> {
+        let index = self.children_index();
+        let stack = self
+            .roots()
+            .into_iter()
+            .rev()
+            .map(|root| (0, root))
+            .collect();
+
+        TreeIter {
+            index,
+            stack,
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Every open span, ordered so that each parent comes before its
+    /// children: first the [roots](SpanSnapshot::roots), orphans included,
+    /// then their children a generation at a time, in creation order within
+    /// each generation.
+    ///
+    /// Spans whose parent links form a cycle have no root to be reached
+    /// from. The cycle is cut at its oldest span, which is placed as if it
+    /// were a root once everything reachable from the real roots is listed.
+    pub fn topo_order(&self) -> Vec<&SpanRecord> {
+        let index = self.children_index();
+        let mut order = Vec::with_capacity(self.len());
+        let mut visited = HashSet::new();
+        let mut queue: VecDeque<_> = self.roots().into();
+        let mut by_creation = self.open_spans_by_creation().into_iter();
+        loop {
+            while let Some(span) = queue.pop_front() {
+                if !visited.insert(&span.id) {
+                    continue;
+                }
+                order.push(span);
+                queue.extend(index.get(&Some(&span.id)).into_iter().flatten());
+            }
+            match by_creation.find(|span| !visited.contains(&span.id)) {
+                Some(span) => queue.push_back(span),
+                None => return order,
+            }
+        }
+    }
+
+    /// The longest chain of currently entered spans starting at an entered
+    /// root, root first. Ties go to the chain created first. Empty unless
+    /// [`Builder::track_enters`](crate::Builder::track_enters) is enabled.
+    ///
+    /// Entries nest along the span a span was created in, so this follows
+    /// [`SpanRecord::parent_kind`], contextual parents included, whatever the
+    /// [`Builder::parent_strategy`](crate::Builder::parent_strategy). A span
+    /// entered under a parent that is not itself entered, such as a task
+    /// polled outside its spawning span, does not continue a chain.
+    pub fn hot_path(&self) -> Vec<&SpanRecord> {
+        fn deepest<'a>(
+            index: &HashMap<&span::Id, Vec<&'a SpanRecord>>,
+            span: &'a SpanRecord,
+            visited: &mut HashSet<&'a span::Id>,
+        ) -> Vec<&'a SpanRecord> {
+            let mut best = Vec::new();
+            if visited.insert(&span.id) {
+                for child in index.get(&span.id).into_iter().flatten() {
+                    let path = deepest(index, child, visited);
+                    if path.len() > best.len() {
+                        best = path;
+                    }
+                }
+            }
+            best.insert(0, span);
+            best
+        }
+
+        let entered: Vec<_> = self
+            .open_spans_by_creation()
+            .into_iter()
+            .filter(|span| span.timing.is_entered())
+            .collect();
+        let mut index: HashMap<_, Vec<_>> = HashMap::new();
+        let mut roots = Vec::new();
+        for &span in &entered {
+            match span
+                .parent_kind
+                .id()
+                .filter(|id| self.spans.contains_key(id))
+            {
+                Some(parent) => index.entry(parent).or_default().push(span),
+                None => roots.push(span),
+            }
+        }
+
+        let mut visited = HashSet::new();
+        let mut best = Vec::new();
+        for root in roots {
+            let path = deepest(&index, root, &mut visited);
+            if path.len() > best.len() {
+                best = path;
+            }
+        }
+        best
+    }
+
+    /// Open spans that have no open children, in creation order. These are
+    /// the innermost pieces of work currently in progress.
+    pub fn leaves(&self) -> Vec<&SpanRecord> {
+        let index = self.children_index();
+        self.open_spans_by_creation()
+            .into_iter()
+            .filter(|span| !index.contains_key(&Some(&span.id)))
+            .collect()
+    }
+
+    /// The records with the ids they are stored under.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&span::Id, &SpanRecord)> {
+        self.spans.iter()
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn ids(&self) -> Vec<span::Id> {
+        self.spans.keys().cloned().collect()
+    }
+
+    /// Starts an empty snapshot that continues `source`'s creation sequence.
+    pub(crate) fn empty_like(source: &SpanSnapshot) -> Self {
+        SpanSnapshot {
+            spans: HashMap::new(),
+            next_seq: source.next_seq,
+            taken_at: None,
+            captured_at: None,
+        }
+    }
+
+    pub(crate) fn set_taken_at(&mut self, taken_at: Instant) {
+        self.taken_at = Some(taken_at);
+        self.captured_at = Some(SystemTime::now());
+    }
+
+    /// Copies the records for `ids` that are still present in `source`.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn copy_from(&mut self, source: &SpanSnapshot, ids: &[span::Id]) {
+        for id in ids {
+            if let Some(span) = source.spans.get(id) {
+                self.spans.insert(id.clone(), span.clone());
+            }
+        }
+    }
+
+    /// Copies only the records matching `keep`.
+    pub(crate) fn copy_matching(&self, mut keep: impl FnMut(&SpanRecord) -> bool) -> Self {
+        let mut copy = SpanSnapshot::empty_like(self);
+        copy.spans.extend(
+            self.spans
+                .iter()
+                .filter(|(_, span)| keep(span))
+                .map(|(id, span)| (id.clone(), span.clone())),
+        );
+        copy
+    }
+
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&SpanRecord) -> bool) {
+        self.spans.retain(|_, span| keep(span));
+    }
+
+    /// Inserts a newly opened span, assigning its creation `seq`.
+    ///
+    /// If the id is already tracked (a subscriber stack delivered
+    /// `on_new_span` twice) the existing record is kept, with the earliest
+    /// timestamps and the new field values added to its own, so its timing,
+    /// event count and extensions survive. Returns whether the span is new.
+    pub(crate) fn insert(&mut self, mut record: SpanRecord) -> bool {
+        if let Some(existing) = self.spans.get_mut(&record.id) {
+            existing.opened_at = existing.opened_at.min(record.opened_at);
+            existing.created_at = existing.created_at.min(record.created_at);
+            existing.fields.merge(record.fields);
+            return false;
+        }
+
+        record.seq = self.next_seq;
+        self.next_seq += 1;
+        self.spans.insert(record.id.clone(), record);
+        true
+    }
+
+    /// Adds `fields`, captured with [`SpanFields::capture_uncapped`], to the
+    /// span's own.
+    /// Sets the just inserted span's [`SpanRecord::sibling_index`] from its
+    /// parent's count of opened children, if the parent is tracked.
+    pub(crate) fn number_sibling(&mut self, id: &span::Id) {
+        let Some(parent) = self.spans.get(id).and_then(|span| span.parent.clone()) else {
+            return;
+        };
+        let Some(parent) = self.spans.get_mut(&parent) else {
+            return;
+        };
+        let index = parent.children_opened;
+        parent.children_opened += 1;
+        if let Some(span) = self.spans.get_mut(id) {
+            span.sibling_index = Some(index);
+        }
+    }
+
+    pub(crate) fn record(&mut self, id: &span::Id, fields: SpanFields, rules: &FieldRules) {
+        if let Some(span) = self.spans.get_mut(id) {
+            span.fields.merge_capped(fields, rules.max_fields);
+        }
+    }
+
+    pub(crate) fn enter(&mut self, id: &span::Id, now: Instant, depth: usize) {
+        if let Some(span) = self.spans.get_mut(id) {
+            span.timing.enter(now, depth);
+            span.was_entered = true;
+        }
+    }
+
+    pub(crate) fn record_event(&mut self, id: &span::Id) {
+        if let Some(span) = self.spans.get_mut(id) {
+            span.events += 1;
+        }
+    }
+
+    pub(crate) fn mark_entered(&mut self, id: &span::Id) {
+        if let Some(span) = self.spans.get_mut(id) {
+            span.was_entered = true;
+        }
+    }
+
+    pub(crate) fn exit(&mut self, id: &span::Id, now: Instant) {
+        if let Some(span) = self.spans.get_mut(id) {
+            span.timing.exit(now);
+        }
+    }
+
+    /// Adds a closed span's record as is, keeping its `seq`.
+    pub(crate) fn insert_closed(&mut self, record: SpanRecord) {
+        self.spans.insert(record.id.clone(), record);
+    }
+
+    pub(crate) fn close_span(&mut self, id: span::Id) -> Option<SpanRecord> {
+        self.spans.remove(&id)
+    }
+}
+
+struct TreeIter<'a> {
+    index: HashMap<Option<&'a span::Id>, Vec<&'a SpanRecord>>,
+    stack: Vec<(usize, &'a SpanRecord)>,
+    visited: HashSet<&'a span::Id>,
+}
+
+impl<'a> Iterator for TreeIter<'a> {
+    type Item = (usize, &'a SpanRecord);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((depth, span)) = self.stack.pop() {
+            if !self.visited.insert(&span.id) {
+                continue;
+            }
+
+            let children = self.index.get(&Some(&span.id)).into_iter().flatten();
+            self.stack
+                .extend(children.rev().map(|child| (depth + 1, *child)));
+            return Some((depth, span));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_open_spans_by_creation() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let first = info_span!("first");
+        let dropped = info_span!("dropped");
+        let second = info_span!("second");
+        drop(dropped);
+        // Reuses the slot freed by `dropped`.
+        let third = info_span!("third");
+
+        let names = span_dumper
+            .snapshot()
+            .open_spans_by_creation()
+            .iter()
+            .map(|span| span.meta.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["first", "second", "third"]);
+
+        drop((first, second, third));
+    }
+
+    #[test]
+    fn test_leaves() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let middle = info_span!(parent: &root, "middle");
+        let _leaf_a = info_span!(parent: &middle, "leaf_a");
+        let _leaf_b = info_span!(parent: &root, "leaf_b");
+        let _lone = info_span!("lone");
+
+        let snapshot = span_dumper.snapshot();
+        let names = snapshot
+            .leaves()
+            .iter()
+            .map(|span| span.meta.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["leaf_a", "leaf_b", "lone"]);
+    }
+
+    #[test]
+    fn test_tree_iter() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let child_a = info_span!(parent: &root, "child_a");
+        let _grandchild = info_span!(parent: &child_a, "grandchild");
+        let _child_b = info_span!(parent: &root, "child_b");
+        let _other_root = info_span!("other_root");
+
+        let snapshot = span_dumper.snapshot();
+        let walk = snapshot
+            .tree_iter()
+            .map(|(depth, span)| (depth, span.meta.name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            walk,
+            [
+                (0, "root"),
+                (1, "child_a"),
+                (2, "grandchild"),
+                (1, "child_b"),
+                (0, "other_root"),
+            ]
+        );
+
+        let first_two = snapshot.tree_iter().take(2).count();
+        assert_eq!(first_two, 2);
+        assert_eq!(snapshot.validate(), []);
+    }
+
+    #[test]
+    fn test_topo_order() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let child = info_span!(parent: &root, "child");
+        let _grandchild = info_span!(parent: &child, "grandchild");
+        let gone = info_span!("gone");
+        let _orphan = info_span!(parent: &gone, "orphan");
+        let a = info_span!("a");
+        let b = info_span!(parent: &a, "b");
+
+        let mut snapshot = span_dumper.snapshot();
+        snapshot.close_span(gone.id().unwrap());
+        snapshot.get_mut(&a.id().unwrap()).unwrap().parent = b.id();
+
+        let names: Vec<_> = snapshot
+            .topo_order()
+            .iter()
+            .map(|span| span.meta.name)
+            .collect();
+        assert_eq!(names, ["root", "orphan", "child", "grandchild", "a", "b"]);
+    }
+
+    #[test]
+    fn test_lowest_common_ancestor() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let child = info_span!(parent: &root, "child");
+        let a = info_span!(parent: &child, "a");
+        let b = info_span!(parent: &root, "b");
+        let other = info_span!("other");
+
+        let snapshot = span_dumper.snapshot();
+        let id = |span: &tracing::Span| span.id().unwrap();
+        let lca = |x: &tracing::Span, y: &tracing::Span| {
+            snapshot
+                .lowest_common_ancestor(&id(x), &id(y))
+                .map(|span| span.meta.name)
+        };
+
+        let ancestors: Vec<_> = snapshot
+            .ancestors(&id(&a))
+            .iter()
+            .map(|span| span.meta.name)
+            .collect();
+        assert_eq!(ancestors, ["child", "root"]);
+        assert_eq!(lca(&a, &b), Some("root"));
+        assert_eq!(lca(&a, &child), Some("child"));
+        assert_eq!(lca(&a, &other), None);
+        assert!(snapshot
+            .lowest_common_ancestor(&id(&a), &tracing::span::Id::from_u64(u64::MAX))
+            .is_none());
+    }
+
+    #[test]
+    fn test_roots() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let first = info_span!("first");
+        let closed = info_span!(parent: &first, "closed");
+        let _orphan = info_span!(parent: &closed, "orphan");
+        let _second = info_span!("second");
+
+        let mut snapshot = span_dumper.snapshot();
+        snapshot.close_span(closed.id().unwrap());
+        let roots: Vec<_> = snapshot.roots().iter().map(|span| span.meta.name).collect();
+        assert_eq!(roots, ["first", "orphan", "second"]);
+    }
+
+    #[test]
+    fn test_child_count() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let child = info_span!(parent: &root, "child");
+        let grandchild = info_span!(parent: &child, "grandchild");
+        let _sibling = info_span!(parent: &root, "sibling");
+
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(snapshot.child_count(&root.id().unwrap()), 2);
+        assert_eq!(snapshot.child_count(&child.id().unwrap()), 1);
+        assert_eq!(snapshot.child_count(&grandchild.id().unwrap()), 0);
+    }
+
+    #[test]
+    fn test_never_entered_spans() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let polled = info_span!("polled");
+        polled.in_scope(|| {});
+        let _never_polled = info_span!("never_polled");
+
+        let snapshot = span_dumper.snapshot();
+        let names: Vec<_> = snapshot
+            .never_entered_spans()
+            .iter()
+            .map(|span| span.meta.name)
+            .collect();
+        assert_eq!(names, ["never_polled"]);
+        assert!(snapshot.get(&polled.id().unwrap()).unwrap().was_entered);
+    }
+
+    #[test]
+    fn test_any_and_find() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let query = info_span!(target: "db", "query");
+        let _request = info_span!(target: "http", "request");
+
+        let snapshot = span_dumper.snapshot();
+        assert!(snapshot.any(|span| span.meta.target == "db"));
+        assert!(!snapshot.any(|span| span.meta.target == "cache"));
+        assert_eq!(
+            snapshot
+                .find(|span| span.meta.target == "db")
+                .map(|span| &span.id),
+            query.id().as_ref()
+        );
+        assert!(snapshot.find(|span| span.meta.name == "missing").is_none());
+    }
+
+    #[test]
+    fn test_targets_and_names() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _request = info_span!(target: "http", "request");
+        let _query = info_span!(target: "db", "query");
+        let _other = info_span!(target: "http", "accept");
+
+        let snapshot = span_dumper.snapshot();
+        assert_eq!(Vec::from_iter(snapshot.targets()), ["db", "http"]);
+        assert_eq!(
+            Vec::from_iter(snapshot.names()),
+            ["accept", "query", "request"]
+        );
+    }
+
+    #[test]
+    fn test_group_by_class() {
+        const IO: u32 = 1;
+        const COMPUTE: u32 = 2;
+
+        let span_dumper = SpanDumpLayer::builder()
+            .with_name_classifier(|name| match name {
+                "read" | "write" => Some(IO),
+                "hash" => Some(COMPUTE),
+                _ => None,
+            })
+            .build();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _spans = [
+            info_span!("read"),
+            info_span!("hash"),
+            info_span!("write"),
+            info_span!("other"),
+        ];
+
+        let snapshot = span_dumper.snapshot();
+        let groups = snapshot.group_by_class();
+        let names = |class| {
+            groups[&class]
+                .iter()
+                .map(|span| span.meta.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(Some(IO)), ["read", "write"]);
+        assert_eq!(names(Some(COMPUTE)), ["hash"]);
+        assert_eq!(names(None), ["other"]);
+        assert_eq!(
+            snapshot
+                .filtered(&crate::SnapshotFilter::new().class(COMPUTE))
+                .len(),
+            1
+        );
+    }
+
+    #[cfg(feature = "tokio-task-id")]
+    #[tokio::test]
+    async fn test_group_by_task() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let _outside = info_span!("outside");
+        let (task, inside) = tokio::spawn(async { (tokio::task::id(), info_span!("inside")) })
+            .await
+            .unwrap();
+
+        let snapshot = span_dumper.snapshot();
+        let groups = snapshot.group_by_task();
+        assert_eq!(groups[&None][0].meta.name, "outside");
+        assert_eq!(groups[&Some(task)][0].id, inside.id().unwrap());
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters describing what the layer has done since it was built.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LayerStats {
+    /// Spans rejected by [`Builder::capture_if`](crate::Builder::capture_if)
+    /// or [`Builder::require_field`](crate::Builder::require_field).
+    pub filtered_count: u64,
+    /// Spans that were inserted into the map.
+    pub tracked_count: u64,
+    /// Spans left out by [`Builder::sample_rate`](crate::Builder::sample_rate).
+    /// The effective rate is `tracked_count` over the sum of the two.
+    pub sampled_out_count: u64,
+    /// Events not delivered to a
+    /// [`SpanDumpLayer::subscribe`](crate::SpanDumpLayer::subscribe) channel
+    /// because it was full.
+    pub dropped_events: u64,
+    /// Times the layer had to wait for its write lock because another
+    /// thread held it. Best-effort and meant for diagnostics only, e.g. to
+    /// tell whether snapshots are slowing down span creation.
+    pub write_lock_contended: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Stats {
+    pub(crate) filtered: AtomicU64,
+    pub(crate) tracked: AtomicU64,
+    pub(crate) sampled_out: AtomicU64,
+    /// Spans considered by the sampler, used to pick which ones to keep.
+    pub(crate) sample_seen: AtomicU64,
+    pub(crate) dropped_events: AtomicU64,
+    pub(crate) write_contended: AtomicU64,
+    /// Spans considered for [`Builder::capture_backtrace`](crate::Builder::capture_backtrace).
+    #[cfg(feature = "backtrace")]
+    pub(crate) backtrace_seen: AtomicU64,
+}
+
+impl Stats {
+    pub(crate) fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether the next span should be kept when sampling at `rate`, see
+    /// [`keep_sample`].
+    pub(crate) fn sample(&self, rate: f64) -> bool {
+        let keep = keep_sample(&self.sample_seen, rate);
+        if !keep {
+            Stats::incr(&self.sampled_out);
+        }
+        keep
+    }
+
+    pub(crate) fn load(&self) -> LayerStats {
+        LayerStats {
+            filtered_count: self.filtered.load(Ordering::Relaxed),
+            tracked_count: self.tracked.load(Ordering::Relaxed),
+            sampled_out_count: self.sampled_out.load(Ordering::Relaxed),
+            dropped_events: self.dropped_events.load(Ordering::Relaxed),
+            write_lock_contended: self.write_contended.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Whether to keep the next of the items counted by `seen` when sampling at
+/// `rate`. Items are picked by counting rather than at random, so exactly
+/// one in `1 / rate` is kept.
+pub(crate) fn keep_sample(seen: &AtomicU64, rate: f64) -> bool {
+    let n = seen.fetch_add(1, Ordering::Relaxed) as f64;
+    ((n + 1.0) * rate).floor() > (n * rate).floor()
+}
@@ -0,0 +1,192 @@
+//! A minimal binary stream of [`SpanEvent`]s for live tailing a process
+//! from another one, see [`SpanDumpLayer::serve_tail`].
+//!
+//! Each frame is a big-endian `u32` byte length followed by a bincode
+//! encoded [`TailEvent`] of at most [`MAX_FRAME_LEN`] bytes. This is a
+//! debugging aid: there is no handshake or versioning, and events the
+//! reader is too slow for are dropped.
+
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dto::unix_millis;
+use crate::{SpanDumpError, SpanDumpLayer, SpanEvent};
+
+/// The largest frame payload [`read_frame`] accepts and [`write_frame`]
+/// writes, so a corrupt length prefix cannot make the reader allocate
+/// gigabytes.
+pub const MAX_FRAME_LEN: u32 = 1 << 20;
+
+/// A [`SpanEvent`] as sent over the tail stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TailEvent {
+    Opened {
+        id: u64,
+        parent: Option<u64>,
+        name: String,
+        target: String,
+        level: String,
+        created_at_ms: u64,
+    },
+    Closed {
+        id: u64,
+    },
+}
+
+impl From<&SpanEvent> for TailEvent {
+    fn from(event: &SpanEvent) -> Self {
+        match event {
+            SpanEvent::Opened(record) => TailEvent::Opened {
+                id: record.id.into_u64(),
+                parent: record.parent.as_ref().map(|id| id.into_u64()),
+                name: record.meta.name.to_string(),
+                target: record.meta.target.to_string(),
+                level: record.meta.level.to_string(),
+                created_at_ms: unix_millis(record.created_at),
+            },
+            SpanEvent::Closed(id) => TailEvent::Closed { id: id.into_u64() },
+        }
+    }
+}
+
+/// Writes `event` as one length-prefixed frame.
+pub fn write_frame<W: Write>(w: &mut W, event: &TailEvent) -> Result<(), SpanDumpError> {
+    let payload = bincode::serialize(event)?;
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|&len| len <= MAX_FRAME_LEN)
+        .ok_or_else(|| frame_too_large(payload.len()))?;
+    w.write_all(&len.to_be_bytes())?;
+    Ok(w.write_all(&payload)?)
+}
+
+/// Reads one frame written by [`write_frame`], or `None` at a clean end of
+/// the stream.
+pub fn read_frame<R: Read>(r: &mut R) -> Result<Option<TailEvent>, SpanDumpError> {
+    let mut len = [0; 4];
+    match r.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_LEN {
+        return Err(frame_too_large(len as usize));
+    }
+    let mut payload = vec![0; len as usize];
+    r.read_exact(&mut payload)?;
+    Ok(Some(bincode::deserialize(&payload)?))
+}
+
+fn frame_too_large(len: usize) -> SpanDumpError {
+    SpanDumpError::Serialize(
+        format!(
+            "tail frame of {} bytes exceeds the maximum of {}",
+            len, MAX_FRAME_LEN
+        )
+        .into(),
+    )
+}
+
+impl SpanDumpLayer {
+    /// Accepts a single connection on `listener` and streams a
+    /// [`write_frame`] for every span event to it until the connection
+    /// fails or is closed, which is returned as an error.
+    ///
+    /// This blocks, so run it on its own thread. Events are collected from
+    /// the moment it is called, through a [`SpanDumpLayer::subscribe`]
+    /// channel, so events the connection cannot keep up with are dropped
+    /// rather than slowing down tracing.
+    pub fn serve_tail(&self, listener: TcpListener) -> Result<(), SpanDumpError> {
+        let events = self.subscribe();
+        let (stream, _) = listener.accept()?;
+        let mut stream = io::BufWriter::new(stream);
+
+        for event in events {
+            write_frame(&mut stream, &TailEvent::from(&event))?;
+            stream.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+    use std::sync::atomic::Ordering;
+
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+
+    #[test]
+    fn test_serve_tail() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn({
+            let span_dumper = span_dumper.clone();
+            move || span_dumper.serve_tail(listener)
+        });
+        while span_dumper.shared.subscribers.len.load(Ordering::Relaxed) == 0 {
+            std::thread::yield_now();
+        }
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let span = info_span!("tailed");
+        let id = span.id().unwrap().into_u64();
+        drop(span);
+
+        match read_frame(&mut client).unwrap().unwrap() {
+            TailEvent::Opened {
+                id: opened, name, ..
+            } => {
+                assert_eq!(opened, id);
+                assert_eq!(name, "tailed");
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+        assert_eq!(
+            read_frame(&mut client).unwrap(),
+            Some(TailEvent::Closed { id })
+        );
+    }
+
+    #[test]
+    fn test_read_frame_eof() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &TailEvent::Closed { id: 7 }).unwrap();
+        let mut reader = buf.as_slice();
+        assert_eq!(
+            read_frame(&mut reader).unwrap(),
+            Some(TailEvent::Closed { id: 7 })
+        );
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_frame_too_large() {
+        let mut buf = (MAX_FRAME_LEN + 1).to_be_bytes().to_vec();
+        buf.extend_from_slice(&[0; 16]);
+        let err = read_frame(&mut buf.as_slice()).unwrap_err();
+        assert!(matches!(err, SpanDumpError::Serialize(_)));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "serialization error: tail frame of {} bytes exceeds the maximum of {}",
+                MAX_FRAME_LEN + 1,
+                MAX_FRAME_LEN
+            )
+        );
+    }
+}
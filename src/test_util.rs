@@ -0,0 +1,311 @@
+use std::panic::{self, PanicHookInfo};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::{SpanDumpLayer, SpanSnapshot, TreeStyle};
+
+type PanicHook = Box<dyn Fn(&PanicHookInfo<'_>) + Send + Sync>;
+
+/// Restores the panic hook that was installed before
+/// [`SpanDumpLayer::capture_on_panic`] when dropped.
+///
+/// The hook cannot be replaced while the thread is panicking, so a guard
+/// dropped during unwinding instead leaves its hook in place, only running
+/// the previous one from then on.
+#[must_use = "the panic hook is restored as soon as the guard is dropped"]
+pub struct PanicDumpGuard {
+    previous: Arc<PanicHook>,
+    armed: Arc<AtomicBool>,
+}
+
+impl Drop for PanicDumpGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.armed.store(false, Ordering::Release);
+            return;
+        }
+        let previous = self.previous.clone();
+        drop(panic::take_hook());
+        panic::set_hook(Box::new(move |info| previous(info)));
+    }
+}
+
+impl SpanDumpLayer {
+    /// Installs a panic hook that prints the open spans as a tree to stderr
+    /// before running the previous hook, until the returned guard is dropped.
+    ///
+    /// Panic hooks are process wide, so nested guards must be dropped in
+    /// reverse order, and the spans are also dumped for panics on other
+    /// threads, e.g. in other tests running in parallel.
+    pub fn capture_on_panic(&self) -> PanicDumpGuard {
+        self.capture_on_panic_with(|dump| eprintln!("{}", dump))
+    }
+
+    fn capture_on_panic_with(&self, sink: impl Fn(&str) + Send + Sync + 'static) -> PanicDumpGuard {
+        let previous: Arc<PanicHook> = Arc::new(panic::take_hook());
+        let armed = Arc::new(AtomicBool::new(true));
+        let layer = self.clone();
+        panic::set_hook(Box::new({
+            let previous = previous.clone();
+            let armed = armed.clone();
+            move |info| {
+                // The panic may have happened with the span lock held on
+                // this thread, so never block on it here.
+                let snapshot = armed
+                    .load(Ordering::Acquire)
+                    .then(|| layer.try_snapshot())
+                    .flatten();
+                if let Some(snapshot) = snapshot {
+                    sink(&format!("open spans at panic:\n{}", tree(&snapshot)));
+                }
+                previous(info)
+            }
+        }));
+        PanicDumpGuard { previous, armed }
+    }
+
+    fn try_snapshot(&self) -> Option<SpanSnapshot> {
+        let spans = match self.shared.spans.try_read() {
+            Ok(spans) => spans,
+            Err(std::sync::TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(std::sync::TryLockError::WouldBlock) => return None,
+        };
+        Some(spans.clone())
+    }
+
+    /// Panics if any spans are still open, listing the leaked spans as a
+    /// tree. Meant for test teardown.
+    pub fn assert_empty(&self) {
+        let snapshot = self.snapshot();
+        if snapshot.is_empty() {
+            return;
+        }
+
+        panic!(
+            "expected no open spans, but {} are still open:\n{}",
+            snapshot.len(),
+            tree(&snapshot)
+        );
+    }
+}
+
+/// Panics unless the open spans of `snapshot` form exactly the tree of span
+/// names in `expected`, ignoring ids and everything but the names, e.g.
+/// `assert_tree_matches(&snapshot, "outer\n  inner")`.
+///
+/// `expected` has one name per line, children indented further than their
+/// parent and siblings in creation order. Any consistent indentation works,
+/// and blank lines and common leading indentation are ignored. On mismatch
+/// both trees are printed with the differing lines marked.
+#[track_caller]
+pub fn assert_tree_matches(snapshot: &SpanSnapshot, expected: &str) {
+    let actual: Vec<_> = snapshot
+        .tree_iter()
+        .map(|(depth, span)| (depth, span.meta.name.to_string()))
+        .collect();
+    let expected = parse_tree(expected);
+    if actual == expected {
+        return;
+    }
+
+    let render = |&(depth, ref name): &(usize, String)| format!("{}{}", "  ".repeat(depth), name);
+    let mut diff = String::new();
+    for i in 0..actual.len().max(expected.len()) {
+        match (expected.get(i).map(render), actual.get(i).map(render)) {
+            (Some(expected), Some(actual)) if expected == actual => {
+                diff.push_str(&format!("  {}\n", actual))
+            }
+            (expected, actual) => {
+                if let Some(expected) = expected {
+                    diff.push_str(&format!("- {}\n", expected));
+                }
+                if let Some(actual) = actual {
+                    diff.push_str(&format!("+ {}\n", actual));
+                }
+            }
+        }
+    }
+    panic!("span tree does not match (- expected, + actual):\n{}", diff);
+}
+
+/// Parses an indented tree of names into `(depth, name)` pairs in order.
+fn parse_tree(tree: &str) -> Vec<(usize, String)> {
+    let mut indents: Vec<usize> = Vec::new();
+    let mut nodes = Vec::new();
+    for line in tree.lines().filter(|line| !line.trim().is_empty()) {
+        let indent = line.len() - line.trim_start().len();
+        while indents.last().is_some_and(|&last| last > indent) {
+            indents.pop();
+        }
+        if indents.last().is_none_or(|&last| last < indent) {
+            indents.push(indent);
+        }
+        nodes.push((indents.len() - 1, line.trim().to_string()));
+    }
+    nodes
+}
+
+fn tree(snapshot: &SpanSnapshot) -> String {
+    let mut tree = Vec::new();
+    snapshot
+        .dump_tree_to(&mut tree, &TreeStyle::default())
+        .expect("writing to a Vec cannot fail");
+    String::from_utf8_lossy(&tree).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_assert_empty_passes() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        drop(info_span!("closed").entered());
+        span_dumper.assert_empty();
+    }
+
+    #[test]
+    #[should_panic(expected = "2 are still open:\nleaked (leak) id=")]
+    fn test_assert_empty_panics() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let leaked = info_span!(target: "leak", "leaked");
+        let _child = info_span!(target: "leak", parent: &leaked, "child");
+        span_dumper.assert_empty();
+    }
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    /// Records `label` and the dump, ignoring panics from other threads,
+    /// which may come from other tests running while the hooks are
+    /// installed.
+    fn recorder(log: &Log, label: &'static str) -> impl Fn(&str) + Send + Sync + 'static {
+        let log = log.clone();
+        let thread = thread::current().id();
+        move |dump| {
+            if thread::current().id() == thread {
+                log.lock().unwrap().push(format!("{}: {}", label, dump));
+            }
+        }
+    }
+
+    fn take(log: &Log) -> Vec<String> {
+        std::mem::take(&mut log.lock().unwrap())
+    }
+
+    #[test]
+    fn test_capture_on_panic() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let log = Log::default();
+        let original = panic::take_hook();
+        let record = recorder(&log, "original");
+        panic::set_hook(Box::new(move |_| record("")));
+
+        let open = info_span!("open");
+        let outer = span_dumper.capture_on_panic_with(recorder(&log, "outer"));
+        let inner = span_dumper.capture_on_panic_with(recorder(&log, "inner"));
+        let _ = panic::catch_unwind(|| panic!("nested"));
+        let dump = format!(
+            "open spans at panic:\nopen ({}) id={}\n",
+            module_path!(),
+            open.id().unwrap().into_u64()
+        );
+        assert_eq!(
+            take(&log),
+            [
+                format!("inner: {}", dump),
+                format!("outer: {}", dump),
+                "original: ".to_string()
+            ]
+        );
+
+        drop(inner);
+        let _ = panic::catch_unwind(|| panic!("outer only"));
+        assert_eq!(
+            take(&log),
+            [format!("outer: {}", dump), "original: ".to_string()]
+        );
+
+        drop(outer);
+        let _ = panic::catch_unwind(|| panic!("restored"));
+        assert_eq!(take(&log), ["original: "]);
+
+        // Unwinding drops the guard while panicking, which must not panic
+        // again, and disarms its hook.
+        let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _guard = span_dumper.capture_on_panic_with(recorder(&log, "unwound"));
+            panic!("through guard")
+        }));
+        assert_eq!(
+            take(&log),
+            [format!("unwound: {}", dump), "original: ".to_string()]
+        );
+        let _ = panic::catch_unwind(|| panic!("disarmed"));
+        assert_eq!(take(&log), ["original: "]);
+
+        panic::set_hook(original);
+    }
+
+    #[test]
+    fn test_assert_tree_matches() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let outer = info_span!("outer");
+        let inner = info_span!(parent: &outer, "inner");
+        let _leaf = info_span!(parent: &inner, "leaf");
+        let _sibling = info_span!(parent: &outer, "sibling");
+        let _other = info_span!("other");
+
+        let snapshot = span_dumper.snapshot();
+        assert_tree_matches(
+            &snapshot,
+            "
+            outer
+                inner
+                    leaf
+                sibling
+            other
+            ",
+        );
+
+        let message = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            assert_tree_matches(&snapshot, "outer\n  inner")
+        }))
+        .unwrap_err()
+        .downcast::<String>()
+        .unwrap();
+        assert_eq!(
+            *message,
+            "span tree does not match (- expected, + actual):\n  outer\n    inner\n\
+             +     leaf\n+   sibling\n+ other\n"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::{HashMap, HashSet};
+
+use tracing::span;
+
+use crate::SpanSnapshot;
+
+/// An anomaly found by [`SpanSnapshot::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SnapshotWarning {
+    /// The span's parent is not open. This happens normally when a parent
+    /// closes before its children, but may also point at missed spans.
+    Orphan { id: span::Id, parent: span::Id },
+    /// Following parent links from the span leads back to it.
+    Cycle { id: span::Id },
+    /// The span's record is stored under another id, or shares its creation
+    /// sequence number with another record.
+    Duplicate { id: span::Id },
+    /// The span opened after the snapshot was taken, or has been entered for
+    /// longer than it has been open, e.g. because of a misbehaving clock.
+    NegativeDuration { id: span::Id },
+}
+
+impl SpanSnapshot {
+    /// Checks the snapshot for anomalies, returning one warning per problem
+    /// found, in creation order of the spans involved. An empty list means
+    /// the snapshot is consistent.
+    ///
+    /// Meant as a self-check for tests and when debugging odd subscriber
+    /// behavior; it walks every span's parent chain, so it is not cheap.
+    pub fn validate(&self) -> Vec<SnapshotWarning> {
+        let mut warnings = Vec::new();
+        let mut seqs: HashMap<u64, &span::Id> = HashMap::new();
+        let now = self.now();
+
+        let mut entries: Vec<_> = self.entries().collect();
+        entries.sort_by_key(|(_, span)| span.seq);
+        for (key, span) in entries {
+            if *key != span.id || seqs.insert(span.seq, &span.id).is_some() {
+                warnings.push(SnapshotWarning::Duplicate {
+                    id: span.id.clone(),
+                });
+            }
+        }
+
+        for span in self.open_spans_by_creation() {
+            if let Some(parent) = &span.parent {
+                if self.get(parent).is_none() {
+                    warnings.push(SnapshotWarning::Orphan {
+                        id: span.id.clone(),
+                        parent: parent.clone(),
+                    });
+                }
+            }
+
+            let mut visited = HashSet::new();
+            let mut current = span;
+            while let Some(parent) = current.parent.as_ref().and_then(|id| self.get(id)) {
+                if parent.id == span.id {
+                    warnings.push(SnapshotWarning::Cycle {
+                        id: span.id.clone(),
+                    });
+                    break;
+                }
+                if !visited.insert(&parent.id) {
+                    break;
+                }
+                current = parent;
+            }
+
+            if span.opened_at > now || span.busy_duration_at(now) > span.age_at(now) {
+                warnings.push(SnapshotWarning::NegativeDuration {
+                    id: span.id.clone(),
+                });
+            }
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use super::*;
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_validate() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let root = info_span!("root");
+        let child = info_span!(parent: &root, "child");
+        let leaf = info_span!(parent: &child, "leaf");
+        let (root_id, child_id, leaf_id) =
+            (root.id().unwrap(), child.id().unwrap(), leaf.id().unwrap());
+
+        let mut snapshot = span_dumper.snapshot();
+        assert_eq!(snapshot.validate(), []);
+
+        snapshot.close_span(root_id.clone());
+        assert_eq!(
+            snapshot.validate(),
+            [SnapshotWarning::Orphan {
+                id: child_id.clone(),
+                parent: root_id,
+            }]
+        );
+
+        snapshot.get_mut(&child_id).unwrap().parent = Some(leaf_id.clone());
+        assert_eq!(
+            snapshot.validate(),
+            [
+                SnapshotWarning::Cycle { id: child_id },
+                SnapshotWarning::Cycle { id: leaf_id }
+            ]
+        );
+    }
+}
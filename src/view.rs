@@ -0,0 +1,87 @@
+use tracing::span;
+
+use crate::{SpanKey, SpanRecord, SpanSnapshot};
+
+/// A read-only view of an open span that borrows from a snapshot instead
+/// of copying anything.
+///
+/// Views are meant for cheap scans of the live span map through
+/// [`SpanDumpLayer::with_snapshot`](crate::SpanDumpLayer::with_snapshot):
+/// there the snapshot, and so every view of it, borrows from the layer's
+/// read lock guard, so views cannot escape the closure and new spans wait
+/// until it returns. Copy out what is needed, or take an owned
+/// [`SpanDumpLayer::snapshot`](crate::SpanDumpLayer::snapshot) instead.
+#[derive(Debug, Clone, Copy)]
+pub struct SpanView<'a> {
+    record: &'a SpanRecord,
+}
+
+impl<'a> SpanView<'a> {
+    pub fn id(&self) -> &'a span::Id {
+        &self.record.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.record.meta.name
+    }
+
+    pub fn target(&self) -> &'static str {
+        self.record.meta.target
+    }
+
+    pub fn level(&self) -> tracing::Level {
+        self.record.meta.level
+    }
+
+    pub fn parent(&self) -> Option<&'a span::Id> {
+        self.record.parent.as_ref()
+    }
+
+    /// The full record, for anything the view does not expose.
+    pub fn record(&self) -> &'a SpanRecord {
+        self.record
+    }
+}
+
+impl SpanSnapshot {
+    /// Views of the open spans, in no particular order.
+    pub fn views(&self) -> impl Iterator<Item = SpanView<'_>> {
+        self.open_spans().map(|record| SpanView { record })
+    }
+
+    pub fn view(&self, id: &impl SpanKey) -> Option<SpanView<'_>> {
+        self.get(id).map(|record| SpanView { record })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing::info_span;
+    use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use crate::SpanDumpLayer;
+
+    #[test]
+    fn test_views() {
+        let span_dumper = SpanDumpLayer::new();
+
+        let _sub = tracing_subscriber::registry()
+            .with(span_dumper.clone())
+            .set_default();
+
+        let parent = info_span!(target: "app", "parent");
+        let child = info_span!(parent: &parent, "child");
+
+        let child_id = child.id().unwrap();
+        let (parent_name, count) = span_dumper.with_snapshot(|snapshot| {
+            let view = snapshot.view(&child_id).unwrap();
+            assert_eq!(view.name(), "child");
+            assert_eq!(view.id(), &child_id);
+            let parent = snapshot.view(view.parent().unwrap()).unwrap();
+            assert_eq!(parent.target(), "app");
+            (parent.name(), snapshot.views().count())
+        });
+        assert_eq!((parent_name, count), ("parent", 2));
+    }
+}
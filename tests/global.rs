@@ -0,0 +1,22 @@
+use tracing::info_span;
+use tracing_span_dump::{global, init_global};
+
+#[test]
+fn test_init_global() {
+    assert!(global().is_none());
+
+    let layers: Vec<_> = std::thread::scope(|s| {
+        let threads: Vec<_> = (0..4).map(|_| s.spawn(init_global)).collect();
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap().unwrap())
+            .collect()
+    });
+    let layer = global().unwrap();
+    assert!(layers.iter().all(|other| std::ptr::eq(layer, *other)));
+    assert!(std::ptr::eq(layer, init_global().unwrap()));
+    assert_eq!(layer.dispatch_id(), None);
+
+    let _s = info_span!("test").entered();
+    assert_eq!(layer.count(), 1);
+}